@@ -32,14 +32,14 @@ fn main() {
         .and_then(move |stream| {
             println!("Got stream: {:?}", stream);
             // Create a client on top of the connection
-            let client = RlsClient::new(stream.framed(LspCodec), &handle);
+            let client = RlsClient::new(stream.framed(LspCodec::default()), &handle);
 
             // `RlsClient` has a special method to call the initialize function, and
             // then wait until the building/indexing has finished before sending further messages.
-            client.initialize_and_wait(init_params(&format!("file://{}", &path.display())))
+            client.initialize_and_wait(init_params(uri::path_to_uri(&path).unwrap()))
             .and_then(move |(mut client, resp)| {
                 println!("Received init response: {:#?}", resp);
-                client.document_symbols(doc_params(&format!("file://{}/{}", &path.display(), "src/codec.rs")))
+                client.document_symbols(doc_params(uri::path_to_uri(&path.join("src/codec.rs")).unwrap()))
             })
             .and_then(|resp| {
                 println!("Got document symbols: {:#?}", resp);
@@ -50,25 +50,15 @@ fn main() {
     core.run(request).unwrap();
 }
 
-fn init_params(root: &str) -> ls_types::InitializeParams {
-    ls_types::InitializeParams { 
-        process_id: None, 
-        root_uri: Url::parse(root).ok(),
-        root_path: None,
-        initialization_options: None,
-        capabilities: ls_types::ClientCapabilities {
-            workspace: None,
-            text_document: None,
-            experimental: None,
-        },
-        trace: Some(ls_types::TraceOption::Verbose),
-    }
+fn init_params(root: Url) -> ls_types::InitializeParams {
+    InitializeParamsBuilder::new()
+        .root_uri(root)
+        .trace(ls_types::TraceOption::Verbose)
+        .build()
 }
 
-fn doc_params(file: &str) -> ls_types::DocumentSymbolParams {
+fn doc_params(file: Url) -> ls_types::DocumentSymbolParams {
     ls_types::DocumentSymbolParams {
-        text_document: ls_types::TextDocumentIdentifier::new(
-            Url::parse(file).unwrap(),
-        ),
+        text_document: ls_types::TextDocumentIdentifier::new(file),
     }
 }
@@ -0,0 +1,141 @@
+//! Coalescing of the `textDocument/didChange` notifications sent while a
+//! document is edited quickly, e.g. on every keystroke.
+
+use futures::Future;
+use ls_types::*;
+use tokio_core::reactor::Timeout;
+use url::Url;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::Duration;
+
+use lsp::LspClient;
+use super::Client;
+use super::super::LspError;
+
+/// Sends `textDocument/didChange` for each document at most once per
+/// `window`, combining the changes made in the meantime.
+///
+/// A change replacing the whole document supersedes the changes before it,
+/// so with full sync only the latest text is sent. The combined notification
+/// carries the version of the last change, so versions keep increasing.
+#[derive(Clone)]
+pub struct DebouncedChanges {
+    client: Client,
+    window: Duration,
+    pending: Rc<RefCell<HashMap<Url, DidChangeTextDocumentParams>>>,
+}
+
+impl DebouncedChanges {
+    pub fn new(client: Client, window: Duration) -> Self {
+        DebouncedChanges {
+            client,
+            window,
+            pending: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    /// The client the changes are sent with.
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// Queue `params`, to be sent once `window` has passed since the oldest
+    /// change to the document which is still queued.
+    pub fn did_change(&mut self, params: DidChangeTextDocumentParams) -> Result<(), LspError> {
+        let uri = params.text_document.uri.clone();
+        {
+            let mut pending = self.pending.borrow_mut();
+            if let Some(queued) = pending.get_mut(&uri) {
+                merge(queued, params);
+                return Ok(());
+            }
+            pending.insert(uri.clone(), params);
+        }
+        let timer = Timeout::new(self.window, &self.client.handle).map_err(LspError::Transport)?;
+        let mut client = self.client.clone();
+        let pending = self.pending.clone();
+        self.client.handle.spawn(timer.then(move |_| {
+            let params = pending.borrow_mut().remove(&uri);
+            if let Some(params) = params {
+                if let Err(e) = client.did_change_text_document(params) {
+                    warn!("failed to send textDocument/didChange: {}", e);
+                }
+            }
+            Ok(())
+        }));
+        Ok(())
+    }
+
+    /// Send the queued changes to every document right away, e.g. before a
+    /// request which depends on them.
+    pub fn flush(&mut self) -> Result<(), LspError> {
+        let pending: Vec<_> = self.pending.borrow_mut().drain().map(|(_, params)| params).collect();
+        for params in pending {
+            self.client.did_change_text_document(params)?;
+        }
+        Ok(())
+    }
+}
+
+/// Add the changes of `params` to those already `queued` for the document.
+fn merge(queued: &mut DidChangeTextDocumentParams, params: DidChangeTextDocumentParams) {
+    queued.text_document = params.text_document;
+    match params.content_changes.iter().rposition(|change| change.range.is_none()) {
+        Some(idx) => {
+            queued.content_changes.clear();
+            queued.content_changes.extend(params.content_changes.into_iter().skip(idx));
+        },
+        None => queued.content_changes.extend(params.content_changes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc::Message;
+    use params::DidChangeBuilder;
+    use test_util;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn test_only_last_change_sent() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut debounced = DebouncedChanges::new(Client::new(pipe, &handle), Duration::from_millis(5));
+
+        let mut changes = DidChangeBuilder::new("file:///tmp/main.rs".parse().unwrap(), 1);
+        for text in vec!["f", "fn", "fn main() {}"] {
+            debounced.did_change(changes.replace_all(text)).unwrap();
+        }
+        test_util::settle(&mut core);
+
+        let received = received.borrow();
+        assert_eq!(received.len(), 1);
+        match received[0] {
+            Message::Notification(ref not) => {
+                let params = not.params.as_ref().unwrap();
+                assert_eq!(not.method, "textDocument/didChange");
+                assert_eq!(params["textDocument"]["version"], json!(4));
+                assert_eq!(params["contentChanges"].as_array().map(Vec::len), Some(1));
+                assert_eq!(params["contentChanges"][0]["text"], json!("fn main() {}"));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_incremental_changes_combined() {
+        let uri: Url = "file:///tmp/main.rs".parse().unwrap();
+        let mut changes = DidChangeBuilder::new(uri, 1);
+        let mut queued = changes.edit(Range::new(Position::new(0, 0), Position::new(0, 0)), "f");
+        merge(&mut queued, changes.edit(Range::new(Position::new(0, 1), Position::new(0, 1)), "n"));
+        assert_eq!(queued.text_document.version, 3);
+        let texts: Vec<_> = queued.content_changes.iter().map(|change| change.text.as_str()).collect();
+        assert_eq!(texts, vec!["f", "n"]);
+    }
+}
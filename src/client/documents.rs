@@ -0,0 +1,172 @@
+//! Tracking of the documents open on the server, so that their versions
+//! are kept in step with the notifications sent.
+
+use ls_types::*;
+use url::Url;
+
+use std::collections::HashMap;
+
+use lsp::LspClient;
+use params::DidChangeBuilder;
+use position::PositionMapper;
+use super::Client;
+use super::super::LspError;
+
+/// Sends `textDocument/didOpen`, `didChange` and `didClose` for documents,
+/// numbering their versions from 1 on opening and keeping their text.
+pub struct DocumentTracker {
+    client: Client,
+    documents: HashMap<Url, Document>,
+}
+
+struct Document {
+    changes: DidChangeBuilder,
+    text: String,
+}
+
+impl DocumentTracker {
+    pub fn new(client: Client) -> Self {
+        DocumentTracker {
+            client,
+            documents: HashMap::new(),
+        }
+    }
+
+    /// The client the notifications are sent with.
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// The current version of the open document `uri`.
+    pub fn version(&self, uri: &Url) -> Option<u64> {
+        self.documents.get(uri).map(|document| document.changes.document().version)
+    }
+
+    /// The current text of the open document `uri`.
+    pub fn text(&self, uri: &Url) -> Option<&str> {
+        self.documents.get(uri).map(|document| document.text.as_str())
+    }
+
+    /// Open the document `uri` with `text`, as version 1.
+    pub fn open(&mut self, uri: Url, language_id: &str, text: String) -> Result<(), LspError> {
+        if self.documents.contains_key(&uri) {
+            return Err(LspError::InvalidParams(format!("{} is already open", uri)));
+        }
+        self.client.did_open_text_document(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), language_id.to_string(), 1, text.clone()),
+        })?;
+        self.documents.insert(uri.clone(), Document {
+            changes: DidChangeBuilder::new(uri, 1),
+            text,
+        });
+        Ok(())
+    }
+
+    /// Replace the whole text of `uri`, for servers using full sync.
+    pub fn replace(&mut self, uri: &Url, text: String) -> Result<(), LspError> {
+        let mut changes = self.document(uri)?.changes.clone();
+        let params = changes.replace_all(text.clone());
+        self.client.did_change_text_document(params)?;
+        // Only once the change is on its way, so that a failed notification
+        // leaves the document as the server knows it
+        let document = self.document(uri)?;
+        document.changes = changes;
+        document.text = text;
+        Ok(())
+    }
+
+    /// Replace the text within `range` of `uri`, for servers using
+    /// incremental sync.
+    ///
+    /// The range is counted in the position encoding picked by the server.
+    pub fn edit(&mut self, uri: &Url, range: Range, text: &str) -> Result<(), LspError> {
+        let encoding = self.client.position_encoding();
+        let (start, end, mut changes) = {
+            let document = self.document(uri)?;
+            let mapper = PositionMapper::new(&document.text, encoding);
+            match (mapper.offset(range.start), mapper.offset(range.end)) {
+                (Some(start), Some(end)) if start <= end => (start, end, document.changes.clone()),
+                _ => return Err(LspError::InvalidParams(format!("{:?} is not within {}", range, uri))),
+            }
+        };
+        let params = changes.edit(range, text);
+        self.client.did_change_text_document(params)?;
+        let document = self.document(uri)?;
+        document.changes = changes;
+        document.text.replace_range(start..end, text);
+        Ok(())
+    }
+
+    /// Close the document `uri`.
+    pub fn close(&mut self, uri: &Url) -> Result<(), LspError> {
+        self.document(uri)?;
+        self.client.did_close_text_document(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+        })?;
+        self.documents.remove(uri);
+        Ok(())
+    }
+
+    fn document(&mut self, uri: &Url) -> Result<&mut Document, LspError> {
+        self.documents.get_mut(uri).ok_or_else(|| LspError::InvalidParams(format!("{} is not open", uri)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonrpc::Message;
+    use serde_json::Value;
+    use test_util;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn test_versions() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut documents = DocumentTracker::new(Client::new(pipe, &handle));
+        let uri: Url = "file:///tmp/main.rs".parse().unwrap();
+
+        documents.open(uri.clone(), "rust", "fn main() {}".to_string()).unwrap();
+        assert_eq!(documents.version(&uri), Some(1));
+        documents.edit(&uri, Range::new(Position::new(0, 3), Position::new(0, 7)), "start").unwrap();
+        assert_eq!(documents.text(&uri), Some("fn start() {}"));
+        documents.replace(&uri, "fn run() {}".to_string()).unwrap();
+        assert_eq!(documents.version(&uri), Some(3));
+        documents.close(&uri).unwrap();
+        assert_eq!(documents.version(&uri), None);
+        assert!(documents.close(&uri).is_err());
+        test_util::settle(&mut core);
+
+        let versions: Vec<_> = received.borrow().iter().map(|msg| match *msg {
+            Message::Notification(ref not) => (not.method.clone(), not.params.as_ref().unwrap()["textDocument"]["version"].clone()),
+            ref m => panic!("unexpected message: {:?}", m),
+        }).collect();
+        assert_eq!(versions, vec![
+            ("textDocument/didOpen".to_string(), json!(1)),
+            ("textDocument/didChange".to_string(), json!(2)),
+            ("textDocument/didChange".to_string(), json!(3)),
+            ("textDocument/didClose".to_string(), Value::Null),
+        ]);
+    }
+
+    #[test]
+    fn test_failed_change() {
+        let core = Core::new().unwrap();
+        let (pipe, _remote) = test_util::pipe();
+        let mut documents = DocumentTracker::new(Client::new(pipe, &core.handle()));
+        let uri: Url = "file:///tmp/main.rs".parse().unwrap();
+
+        // Nothing drains while the reactor is not running, so only the
+        // didOpen fits in the queue
+        documents.client().set_queue_capacity(1);
+        documents.open(uri.clone(), "rust", "fn main() {}".to_string()).unwrap();
+        assert!(documents.edit(&uri, Range::new(Position::new(0, 3), Position::new(0, 7)), "start").is_err());
+        assert!(documents.replace(&uri, "fn run() {}".to_string()).is_err());
+        assert!(documents.close(&uri).is_err());
+        assert_eq!(documents.version(&uri), Some(1));
+        assert_eq!(documents.text(&uri), Some("fn main() {}"));
+    }
+}
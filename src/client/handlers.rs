@@ -0,0 +1,704 @@
+//! Reusable `Server` implementations for handling messages initiated by the
+//! language server.
+//!
+//! These can be passed to `Client::with_notification_handler`, or combined
+//! with other handlers through a `ServerChain`.
+
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use jsonrpc::{message, server, ServerCtl};
+use jsonrpc::message::RpcError;
+use ls_types::*;
+use ls_types::notification::Notification;
+use ls_types::request::Request;
+use serde::de::DeserializeOwned;
+use serde_json::{self, Value};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Parse the parameters of an incoming request, answering with an
+/// "invalid params" error if they don't match.
+pub(crate) fn parse_params<T: DeserializeOwned>(params: &Option<Value>) -> Result<T, RpcError> {
+    serde_json::from_value(params.clone().unwrap_or(Value::Null)).map_err(|e| RpcError {
+        code: -32602,
+        message: format!("Invalid params: {}", e),
+        data: None,
+    })
+}
+
+/// A `Server` implementation which forwards the diagnostics published by the
+/// remote server over a channel.
+pub struct DiagnosticsCollector {
+    sender: UnboundedSender<PublishDiagnosticsParams>,
+}
+
+impl DiagnosticsCollector {
+    /// Create a new collector, along with the receiving end of its channel.
+    pub fn new() -> (Self, UnboundedReceiver<PublishDiagnosticsParams>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (DiagnosticsCollector { sender }, receiver)
+    }
+}
+
+impl server::Server for DiagnosticsCollector {
+    type Success = ();
+    type RpcCallResult = Result<(), message::RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        if method != <lsp_notification!("textDocument/publishDiagnostics") as Notification>::METHOD {
+            return None;
+        }
+        match params.clone().map(|p| serde_json::from_value::<PublishDiagnosticsParams>(p)) {
+            Some(Ok(params)) => Some(self.sender.unbounded_send(params).map_err(|_e| ())),
+            _ => Some(Err(())),
+        }
+    }
+}
+
+/// The parameters of a `$/logTrace` notification.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LogTraceParams {
+    pub message: String,
+    /// Additional information, only sent when the trace is verbose.
+    pub verbose: Option<String>,
+}
+
+/// The `$/logTrace` notification, sent by the server when tracing is enabled.
+pub enum LogTrace {}
+
+impl Notification for LogTrace {
+    type Params = LogTraceParams;
+    const METHOD: &'static str = "$/logTrace";
+}
+
+/// A `Server` implementation which forwards `$/logTrace` notifications over
+/// a channel.
+pub struct TraceCollector {
+    sender: UnboundedSender<LogTraceParams>,
+}
+
+impl TraceCollector {
+    /// Create a new collector, along with the receiving end of its channel.
+    pub fn new() -> (Self, UnboundedReceiver<LogTraceParams>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (TraceCollector { sender }, receiver)
+    }
+}
+
+impl server::Server for TraceCollector {
+    type Success = ();
+    type RpcCallResult = Result<(), message::RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        if method != LogTrace::METHOD {
+            return None;
+        }
+        match params.clone().map(|p| serde_json::from_value::<LogTraceParams>(p)) {
+            Some(Ok(params)) => Some(self.sender.unbounded_send(params).map_err(|_e| ())),
+            _ => Some(Err(())),
+        }
+    }
+}
+
+/// A `Server` implementation which dispatches notifications to the channel
+/// or callback registered for their method.
+///
+/// ```ignore
+/// let router = NotificationRouter::new()
+///     .on::<notification::PublishDiagnostics>(diagnostics)
+///     .handle::<notification::LogMessage, _>(|params| println!("{}", params.message));
+/// ```
+pub struct NotificationRouter {
+    routes: HashMap<&'static str, Box<Fn(Value) -> Result<(), ()>>>,
+}
+
+impl NotificationRouter {
+    pub fn new() -> Self {
+        NotificationRouter {
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Forward the parameters of every `N` notification over `sender`.
+    pub fn on<N>(self, sender: UnboundedSender<N::Params>) -> Self
+        where N: Notification,
+              N::Params: DeserializeOwned + 'static,
+    {
+        self.route::<N, _>(move |params| sender.unbounded_send(params).map_err(|_e| ()))
+    }
+
+    /// Call `handler` with the parameters of every `N` notification.
+    pub fn handle<N, F>(self, handler: F) -> Self
+        where N: Notification,
+              N::Params: DeserializeOwned + 'static,
+              F: Fn(N::Params) + 'static,
+    {
+        self.route::<N, _>(move |params| {
+            handler(params);
+            Ok(())
+        })
+    }
+
+    fn route<N, F>(mut self, route: F) -> Self
+        where N: Notification,
+              N::Params: DeserializeOwned + 'static,
+              F: Fn(N::Params) -> Result<(), ()> + 'static,
+    {
+        self.routes.insert(N::METHOD, Box::new(move |params| {
+            route(serde_json::from_value(params).map_err(|_e| ())?)
+        }));
+        self
+    }
+}
+
+impl Default for NotificationRouter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl server::Server for NotificationRouter {
+    type Success = ();
+    type RpcCallResult = Result<(), message::RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        self.routes.get(method).map(|route| route(params.clone().unwrap_or(Value::Null)))
+    }
+}
+
+/// A message sent by the server for the user.
+#[derive(Debug)]
+pub enum ServerMessage {
+    /// Sent with `window/logMessage`, to be logged.
+    Log(LogMessageParams),
+    /// Sent with `window/showMessage`, to be displayed.
+    Show(ShowMessageParams),
+}
+
+/// A `Server` implementation which forwards `window/logMessage` and
+/// `window/showMessage` notifications over a channel.
+pub struct MessageLogger {
+    sender: UnboundedSender<ServerMessage>,
+}
+
+impl MessageLogger {
+    /// Create a new logger, along with the receiving end of its channel.
+    pub fn new() -> (Self, UnboundedReceiver<ServerMessage>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (MessageLogger { sender }, receiver)
+    }
+}
+
+impl server::Server for MessageLogger {
+    type Success = ();
+    type RpcCallResult = Result<(), message::RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        let msg = if method == <lsp_notification!("window/logMessage") as Notification>::METHOD {
+            serde_json::from_value(params.clone().unwrap_or(Value::Null)).map(ServerMessage::Log)
+        } else if method == <lsp_notification!("window/showMessage") as Notification>::METHOD {
+            serde_json::from_value(params.clone().unwrap_or(Value::Null)).map(ServerMessage::Show)
+        } else {
+            return None;
+        };
+        Some(msg.map_err(|_e| ()).and_then(|msg| self.sender.unbounded_send(msg).map_err(|_e| ())))
+    }
+}
+
+/// The parameters of a `$/progress` notification.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ProgressParams {
+    /// The token identifying the work being reported on.
+    pub token: NumberOrString,
+    pub value: WorkDoneProgress,
+}
+
+/// Work done progress, as reported through `$/progress`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum WorkDoneProgress {
+    Begin {
+        title: String,
+        message: Option<String>,
+        percentage: Option<u32>,
+        cancellable: Option<bool>,
+    },
+    Report {
+        message: Option<String>,
+        percentage: Option<u32>,
+        cancellable: Option<bool>,
+    },
+    End {
+        message: Option<String>,
+    },
+}
+
+/// A `Server` implementation which forwards `$/progress` notifications over
+/// a channel.
+///
+/// This can be combined with the legacy `window/progress` handling of
+/// `WaitForInit` through a `ServerChain`.
+pub struct ProgressCollector {
+    sender: UnboundedSender<ProgressParams>,
+}
+
+impl ProgressCollector {
+    /// Create a new collector, along with the receiving end of its channel.
+    pub fn new() -> (Self, UnboundedReceiver<ProgressParams>) {
+        let (sender, receiver) = mpsc::unbounded();
+        (ProgressCollector { sender }, receiver)
+    }
+}
+
+impl server::Server for ProgressCollector {
+    type Success = ();
+    type RpcCallResult = Result<(), message::RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        if method != "$/progress" {
+            return None;
+        }
+        match params.clone().map(|p| serde_json::from_value::<ProgressParams>(p)) {
+            Some(Ok(params)) => Some(self.sender.unbounded_send(params).map_err(|_e| ())),
+            _ => Some(Err(())),
+        }
+    }
+}
+
+/// A `Server` implementation which answers `workspace/applyEdit` requests from
+/// the remote server using the provided callback.
+///
+/// The callback should apply the edit to the editor's buffers and report
+/// whether it succeeded.
+pub struct ApplyEditHandler<F> {
+    handler: F,
+}
+
+impl<F> ApplyEditHandler<F>
+    where F: Fn(ApplyWorkspaceEditParams) -> ApplyWorkspaceEditResponse
+{
+    pub fn new(handler: F) -> Self {
+        ApplyEditHandler { handler }
+    }
+}
+
+impl<F> server::Server for ApplyEditHandler<F>
+    where F: Fn(ApplyWorkspaceEditParams) -> ApplyWorkspaceEditResponse
+{
+    type Success = ApplyWorkspaceEditResponse;
+    type RpcCallResult = Result<ApplyWorkspaceEditResponse, RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn rpc(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::RpcCallResult> {
+        if method != <lsp_request!("workspace/applyEdit") as Request>::METHOD {
+            return None;
+        }
+        Some(parse_params(params).map(|params| (self.handler)(params)))
+    }
+}
+
+/// A `Server` implementation which answers `window/showMessageRequest`
+/// requests from the remote server using the provided callback.
+///
+/// The callback should present the actions to the user, and return the one
+/// which was picked (if any).
+pub struct ShowMessageRequestHandler<F> {
+    handler: F,
+}
+
+impl<F> ShowMessageRequestHandler<F>
+    where F: Fn(ShowMessageRequestParams) -> Option<MessageActionItem>
+{
+    pub fn new(handler: F) -> Self {
+        ShowMessageRequestHandler { handler }
+    }
+}
+
+impl<F> server::Server for ShowMessageRequestHandler<F>
+    where F: Fn(ShowMessageRequestParams) -> Option<MessageActionItem>
+{
+    type Success = Option<MessageActionItem>;
+    type RpcCallResult = Result<Option<MessageActionItem>, RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn rpc(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::RpcCallResult> {
+        if method != <lsp_request!("window/showMessageRequest") as Request>::METHOD {
+            return None;
+        }
+        Some(parse_params(params).map(|params| (self.handler)(params)))
+    }
+}
+
+/// The parameters of a `workspace/configuration` request.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ConfigurationParams {
+    pub items: Vec<ConfigurationItem>,
+}
+
+/// A configuration section requested through `workspace/configuration`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigurationItem {
+    /// The resource the configuration is requested for.
+    pub scope_uri: Option<String>,
+    /// The name of the configuration section.
+    pub section: Option<String>,
+}
+
+/// A `Server` implementation which answers `workspace/configuration`
+/// requests from the remote server using the provided callback.
+///
+/// The callback should return one value for each requested item, in the
+/// same order.
+pub struct ConfigurationHandler<F> {
+    handler: F,
+}
+
+impl<F> ConfigurationHandler<F>
+    where F: Fn(&[ConfigurationItem]) -> Vec<Value>
+{
+    pub fn new(handler: F) -> Self {
+        ConfigurationHandler { handler }
+    }
+}
+
+impl<F> server::Server for ConfigurationHandler<F>
+    where F: Fn(&[ConfigurationItem]) -> Vec<Value>
+{
+    type Success = Vec<Value>;
+    type RpcCallResult = Result<Vec<Value>, RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn rpc(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::RpcCallResult> {
+        if method != "workspace/configuration" {
+            return None;
+        }
+        Some(parse_params(params).map(|params: ConfigurationParams| (self.handler)(&params.items)))
+    }
+}
+
+/// The capabilities registered by the server through
+/// `client/registerCapability`, see `RegistrationHandler`.
+///
+/// Clones share the same registrations.
+#[derive(Clone, Debug, Default)]
+pub struct Registrations {
+    registrations: Arc<Mutex<Vec<Registration>>>,
+}
+
+impl Registrations {
+    /// The registrations currently in effect.
+    pub fn get(&self) -> Vec<Registration> {
+        self.registrations.lock().unwrap().clone()
+    }
+
+    /// Whether the server has registered support for `method`.
+    pub fn contains(&self, method: &str) -> bool {
+        self.registrations.lock().unwrap().iter().any(|registration| registration.method == method)
+    }
+}
+
+/// A `Server` implementation which acknowledges `client/registerCapability`
+/// and `client/unregisterCapability` requests from the remote server,
+/// keeping track of the registrations.
+///
+/// Pass the registrations to `Client::use_registrations` to have the
+/// capability checks take them into account.
+pub struct RegistrationHandler {
+    registrations: Registrations,
+}
+
+impl RegistrationHandler {
+    /// Create a new handler, along with the registrations it keeps.
+    pub fn new() -> (Self, Registrations) {
+        let registrations = Registrations::default();
+        (RegistrationHandler { registrations: registrations.clone() }, registrations)
+    }
+}
+
+impl server::Server for RegistrationHandler {
+    type Success = ();
+    type RpcCallResult = Result<(), RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn rpc(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::RpcCallResult> {
+        let mut registrations = self.registrations.registrations.lock().unwrap();
+        match method {
+            "client/registerCapability" => Some(parse_params(params).map(|params: RegistrationParams| {
+                registrations.extend(params.registrations);
+            })),
+            "client/unregisterCapability" => Some(parse_params(params).map(|params: UnregistrationParams| {
+                registrations.retain(|registration| {
+                    !params.unregisterations.iter().any(|unregistration| unregistration.id == registration.id)
+                });
+            })),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client::Client;
+    use jsonrpc::Message;
+    use test_util;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn test_collect_diagnostics() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (collector, diagnostics) = DiagnosticsCollector::new();
+        let _client = Client::with_notification_handler(pipe, collector, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "textDocument/publishDiagnostics".to_string(),
+            Some(json!({
+                "uri": "file:///tmp/main.rs",
+                "diagnostics": [{
+                    "range": {
+                        "start": {"line": 0, "character": 0},
+                        "end": {"line": 0, "character": 4},
+                    },
+                    "message": "unused variable",
+                }],
+            })),
+        ))).unwrap();
+
+        let (params, _diagnostics) = test_util::recv(&mut core, diagnostics);
+        assert_eq!(params.uri.as_str(), "file:///tmp/main.rs");
+        assert_eq!(params.diagnostics[0].message, "unused variable");
+    }
+
+    #[test]
+    fn test_collect_trace() {
+        let mut core = Core::new().unwrap();
+        let (pipe, remote) = test_util::pipe();
+        let (collector, traces) = TraceCollector::new();
+        let _client = Client::with_notification_handler(pipe, collector, &core.handle());
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "$/logTrace".to_string(),
+            Some(json!({"message": "Received request 'textDocument/hover'", "verbose": "Params: {}"})),
+        ))).unwrap();
+
+        let (params, _traces) = test_util::recv(&mut core, traces);
+        assert_eq!(params.message, "Received request 'textDocument/hover'");
+        assert_eq!(params.verbose, Some("Params: {}".to_string()));
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let handler = ApplyEditHandler::new(|_params| ApplyWorkspaceEditResponse { applied: true });
+        let _client = Client::with_notification_handler(pipe, handler, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::request(
+            "workspace/applyEdit".to_string(),
+            Some(json!({"edit": {"changes": {}}})),
+        ))).unwrap();
+
+        match test_util::recv(&mut core, remote.from_client).0 {
+            Message::Response(resp) => assert_eq!(resp.result, Ok(json!({"applied": true}))),
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        let begin: ProgressParams = serde_json::from_value(json!({
+            "token": 1,
+            "value": {"kind": "begin", "title": "Indexing", "percentage": 0},
+        })).unwrap();
+        assert_eq!(begin.token, NumberOrString::Number(1));
+        assert_eq!(begin.value, WorkDoneProgress::Begin {
+            title: "Indexing".to_string(),
+            message: None,
+            percentage: Some(0),
+            cancellable: None,
+        });
+
+        let report: ProgressParams = serde_json::from_value(json!({
+            "token": "index",
+            "value": {"kind": "report", "message": "3/4 crates", "percentage": 75},
+        })).unwrap();
+        assert_eq!(report.value, WorkDoneProgress::Report {
+            message: Some("3/4 crates".to_string()),
+            percentage: Some(75),
+            cancellable: None,
+        });
+
+        let end: ProgressParams = serde_json::from_value(json!({
+            "token": "index",
+            "value": {"kind": "end"},
+        })).unwrap();
+        assert_eq!(end.value, WorkDoneProgress::End { message: None });
+    }
+
+    #[test]
+    fn test_collect_progress() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (collector, progress) = ProgressCollector::new();
+        let _client = Client::with_notification_handler(pipe, collector, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "$/progress".to_string(),
+            Some(json!({"token": "index", "value": {"kind": "end"}})),
+        ))).unwrap();
+
+        let (params, _progress) = test_util::recv(&mut core, progress);
+        assert_eq!(params.token, NumberOrString::String("index".to_string()));
+    }
+
+    #[test]
+    fn test_log_message() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (logger, messages) = MessageLogger::new();
+        let _client = Client::with_notification_handler(pipe, logger, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "window/logMessage".to_string(),
+            Some(json!({"type": 1, "message": "cargo check failed"})),
+        ))).unwrap();
+
+        match test_util::recv(&mut core, messages).0 {
+            ServerMessage::Log(params) => {
+                assert_eq!(params.typ, MessageType::Error);
+                assert_eq!(params.message, "cargo check failed");
+            },
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_show_message_request() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let handler = ShowMessageRequestHandler::new(|params: ShowMessageRequestParams| {
+            params.actions.and_then(|actions| actions.into_iter().nth(1))
+        });
+        let _client = Client::with_notification_handler(pipe, handler, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::request(
+            "window/showMessageRequest".to_string(),
+            Some(json!({
+                "type": 3,
+                "message": "Build failed",
+                "actions": [{"title": "Retry"}, {"title": "Ignore"}],
+            })),
+        ))).unwrap();
+
+        match test_util::recv(&mut core, remote.from_client).0 {
+            Message::Response(resp) => assert_eq!(resp.result, Ok(json!({"title": "Ignore"}))),
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_configuration() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let handler = ConfigurationHandler::new(|items: &[ConfigurationItem]| {
+            items.iter().map(|item| json!({"section": item.section})).collect()
+        });
+        let _client = Client::with_notification_handler(pipe, handler, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::request(
+            "workspace/configuration".to_string(),
+            Some(json!({
+                "items": [
+                    {"section": "rust"},
+                    {"scopeUri": "file:///tmp/main.rs", "section": "rust.clippy"},
+                ],
+            })),
+        ))).unwrap();
+
+        match test_util::recv(&mut core, remote.from_client).0 {
+            Message::Response(resp) => assert_eq!(resp.result.unwrap().as_array().unwrap().len(), 2),
+            m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_registrations() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (handler, registrations) = RegistrationHandler::new();
+        let _client = Client::with_notification_handler(pipe, handler, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::request(
+            "client/registerCapability".to_string(),
+            Some(json!({
+                "registrations": [{
+                    "id": "watch-1",
+                    "method": "workspace/didChangeWatchedFiles",
+                    "registerOptions": {"watchers": [{"globPattern": "**/*.rs"}]},
+                }],
+            })),
+        ))).unwrap();
+        let from_client = match test_util::recv(&mut core, remote.from_client) {
+            (Message::Response(resp), from_client) => {
+                assert_eq!(resp.result.unwrap(), Value::Null);
+                from_client
+            },
+            (m, _) => panic!("unexpected message: {:?}", m),
+        };
+        assert!(registrations.contains("workspace/didChangeWatchedFiles"));
+        assert_eq!(registrations.get()[0].id, "watch-1");
+
+        remote.to_client.unbounded_send(Ok(Message::request(
+            "client/unregisterCapability".to_string(),
+            Some(json!({
+                "unregisterations": [{"id": "watch-1", "method": "workspace/didChangeWatchedFiles"}],
+            })),
+        ))).unwrap();
+        match test_util::recv(&mut core, from_client).0 {
+            Message::Response(resp) => assert!(resp.result.is_ok()),
+            m => panic!("unexpected message: {:?}", m),
+        }
+        assert!(!registrations.contains("workspace/didChangeWatchedFiles"));
+    }
+
+    #[test]
+    fn test_notification_router() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (diagnostics_tx, diagnostics) = mpsc::unbounded();
+        let (log_tx, logs) = mpsc::unbounded();
+        let router = NotificationRouter::new()
+            .on::<lsp_notification!("textDocument/publishDiagnostics")>(diagnostics_tx)
+            .on::<lsp_notification!("window/logMessage")>(log_tx);
+        let _client = Client::with_notification_handler(pipe, router, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "window/logMessage".to_string(),
+            Some(json!({"type": 4, "message": "hello"})),
+        ))).unwrap();
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "textDocument/publishDiagnostics".to_string(),
+            Some(json!({"uri": "file:///tmp/main.rs", "diagnostics": []})),
+        ))).unwrap();
+
+        let (log, _logs) = test_util::recv(&mut core, logs);
+        assert_eq!(log.message, "hello");
+        let (params, _diagnostics) = test_util::recv(&mut core, diagnostics);
+        assert_eq!(params.uri.as_str(), "file:///tmp/main.rs");
+    }
+}
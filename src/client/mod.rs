@@ -2,32 +2,103 @@
 //! the `LspClient` trait, and language-specific implementations.
 //! (Currently only for RLS).
 
-use futures::{Future, Sink, Stream};
-use futures::future;
+use futures::{Async, AsyncSink, Future, IntoFuture, Poll, Sink, StartSend, Stream};
+use futures::future::{self, Either, Loop};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::sync::oneshot;
 use ls_types::*;
 use ls_types::notification::Notification;
 use ls_types::request::Request;
-use jsonrpc::{self, server, Endpoint, Message, Parsed};
-use jsonrpc::message::Response;
+use jsonrpc::{self, server, Endpoint, Message, Parsed, ServerCtl};
+use jsonrpc::message::{Response, RpcError};
 use serde;
-use serde_json;
-use tokio_core::reactor::Handle;
+use serde_json::{self, Value};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use url::Url;
 
-use std::io::Error as IoError;
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Error as IoError, Result as IoResult};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::rc::Rc;
 use std::str;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
+use codec::LspCodec;
 use lsp::{InitializeOptions, LspClient};
-use super::custom_err;
+use params::{self, check_formatting_options, ClientInfo};
+use position::PositionEncoding;
+use super::{custom_err, LspError};
+use transport;
+use uri;
 
+pub mod debounce;
+pub mod documents;
+pub mod handlers;
 pub mod rust;
+pub mod rust_analyzer;
 
-pub use self::rust::RlsClient;
+pub use self::debounce::DebouncedChanges;
+pub use self::documents::DocumentTracker;
+pub use self::handlers::{ApplyEditHandler, ConfigurationHandler, DiagnosticsCollector, MessageLogger,
+                         NotificationRouter, ProgressCollector, RegistrationHandler, Registrations,
+                         ShowMessageRequestHandler, TraceCollector};
+pub use self::rust::{ReconnectPolicy, RemoteState, RlsClient};
+pub use self::rust_analyzer::RustAnalyzerClient;
 
 /// A generic async client to a LSP implementation.
+///
+/// Clones are cheap, and share the connection to the server, so that calls
+/// can be made from several tasks at once. Settings such as
+/// `check_capabilities` are copied rather than shared.
+#[derive(Clone)]
 pub struct Client {
-    pub(crate) inner: Option<jsonrpc::Client>,
+    /// Nothing is sent through the jsonrpc client, but it keeps the endpoint
+    /// (and so the connection) running.
+    _endpoint: jsonrpc::Client,
+    handle: Handle,
+    outstanding: Outstanding,
+    /// Every request and notification is sent through this one queue, so
+    /// that they reach the server in the order they were made, as the spec
+    /// requires (e.g. a `didChange` before a `hover` depending on it).
+    /// Responses are matched to requests by id, so any number of requests
+    /// can be in flight at once.
+    outgoing: UnboundedSender<Outgoing>,
+    waiting: Waiting,
+    next_id: Rc<Cell<u64>>,
+    /// Cleared once the connection has closed.
+    alive: Arc<AtomicBool>,
+    observer: Observer,
+    /// The server process, if it was launched by the client.
+    process: transport::Process,
+    /// The capabilities announced by the server in response to `initialize`.
+    capabilities: Rc<RefCell<Option<ServerCapabilities>>>,
+    /// Whether to refuse calls the server did not announce support for.
+    check_capabilities: bool,
+    /// The number of notifications queued by `notify` but not yet sent.
+    queued: Rc<Cell<usize>>,
+    queue_capacity: usize,
+    /// The position encodings to offer in `initialize`.
+    position_encodings: Vec<PositionEncoding>,
+    /// The position encoding picked by the server.
+    position_encoding: Rc<Cell<PositionEncoding>>,
+    /// Sent along with `initialize`.
+    client_info: Option<ClientInfo>,
+    /// Capabilities registered by the server after initializing.
+    registrations: Option<Registrations>,
+    inbox: Inbox,
 }
 
+/// The number of notifications which may be queued before `notify` fails
+/// with `LspError::QueueFull`.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 1024;
+
 impl Client {
     /// Create a new `Client` with the given connection and run futures on the
     /// provided handle.
@@ -37,10 +108,20 @@ impl Client {
             C: Sink<SinkItem = Message, SinkError = IoError>,
             C: Send + 'static,
     {
-        let (client, _fut) = Endpoint::client_only(connection).start(handle);
-        Self {
-            inner: Some(client),
-        }
+        Self::new_with_driver(connection, handle).0
+    }
+
+    /// Like `new`, but also returns the future driving the connection.
+    ///
+    /// The connection is driven on `handle` regardless; the future resolves
+    /// once it has closed.
+    pub fn new_with_driver<C>(connection: C, handle: &Handle) -> (Self, Driver)
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+    {
+        Self::start(connection, handle, |tracked| Endpoint::client_only(tracked).start(handle))
     }
 
     /// Create a new `Client` with a provided handler to handle incoming notifications.
@@ -51,10 +132,689 @@ impl Client {
             C: Send + 'static,
             NH: server::Server + 'static
     {
-        let (client, _fut) = Endpoint::new(connection, notification_handler).start(handle);
-        Self {
-            inner: Some(client),
+        Self::with_notification_handler_and_driver(connection, notification_handler, handle).0
+    }
+
+    /// Like `with_notification_handler`, but also returns the future driving
+    /// the connection, see `new_with_driver`.
+    pub fn with_notification_handler_and_driver<C, NH>(connection: C, notification_handler: NH, handle: &Handle) -> (Self, Driver)
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+            NH: server::Server + 'static
+    {
+        Self::start(connection, handle, |tracked| {
+            let notification_handler = Cancellable { server: notification_handler, inbound: tracked.inbound.clone() };
+            Endpoint::new(tracked, notification_handler).start(handle)
+        })
+    }
+
+    /// Wrap `connection` to track the client's requests, and start the
+    /// jsonrpc endpoint on it with `start`.
+    fn start<C, F>(connection: C, handle: &Handle, start: F) -> (Self, Driver)
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+            F: FnOnce(Tracked<C>) -> (jsonrpc::Client, Driver),
+    {
+        let (tracked, outgoing) = Tracked::new(connection);
+        let outstanding = tracked.outstanding.clone();
+        let waiting = tracked.waiting.clone();
+        let alive = tracked.alive.clone();
+        let observer = tracked.observer.clone();
+        let inbox = tracked.inbox.clone();
+        let (client, driver) = start(tracked);
+        let client = Self {
+            _endpoint: client,
+            handle: handle.clone(),
+            outstanding,
+            outgoing,
+            waiting,
+            next_id: Rc::new(Cell::new(0)),
+            alive,
+            observer,
+            process: transport::Process::default(),
+            capabilities: Rc::new(RefCell::new(None)),
+            check_capabilities: false,
+            queued: Rc::new(Cell::new(0)),
+            queue_capacity: DEFAULT_QUEUE_CAPACITY,
+            position_encodings: Vec::new(),
+            position_encoding: Rc::new(Cell::new(PositionEncoding::Utf16)),
+            client_info: None,
+            registrations: None,
+            inbox,
+        };
+        (client, driver)
+    }
+
+    /// Connect to a server using `connect` and initialize it, retrying with
+    /// exponential backoff if either fails, e.g. for a server which starts
+    /// listening on its socket lazily.
+    ///
+    /// A response to `initialize` which reports an error is not retried.
+    pub fn connect_and_initialize<F, C>(connect: F, params: InitializeParams, policy: ReconnectPolicy, handle: &Handle)
+        -> Box<Future<Item=(Self, Result<InitializeResult, InitializeError>), Error=LspError>>
+        where
+            F: Fn() -> Box<Future<Item=C, Error=IoError>> + 'static,
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+    {
+        let handle = handle.clone();
+        Box::new(future::loop_fn(0, move |attempt| {
+            let client_handle = handle.clone();
+            let retry_handle = handle.clone();
+            let params = params.clone();
+            let policy = policy.clone();
+            connect().map_err(LspError::Transport).and_then(move |connection| {
+                let mut client = Self::new(connection, &client_handle);
+                client.initialize(params).map(move |resp| (client, resp))
+            })
+            .then(move |res| -> Box<Future<Item=Loop<(Self, Result<InitializeResult, InitializeError>), u32>, Error=LspError>> {
+                match res {
+                    Ok(initialized) => Box::new(future::ok(Loop::Break(initialized))),
+                    Err(e) if attempt < policy.max_retries => {
+                        warn!("failed to initialize the server ({}), retrying", e);
+                        match Timeout::new(policy.delay(attempt), &retry_handle) {
+                            Ok(timer) => Box::new(timer.map(move |()| Loop::Continue(attempt + 1)).map_err(LspError::Transport)),
+                            Err(e) => Box::new(future::err(LspError::Transport(e))),
+                        }
+                    },
+                    Err(e) => Box::new(future::err(e)),
+                }
+            })
+        }))
+    }
+
+    /// Launch the language server `cmd` and communicate with it over its
+    /// stdin/stdout.
+    ///
+    /// The process is killed when the client (and all its clones) are dropped.
+    pub fn spawn(cmd: Command, handle: &Handle) -> IoResult<Self> {
+        let (process, connection) = transport::launch(cmd)?;
+        let mut client = Self::new(connection, handle);
+        client.process = process;
+        Ok(client)
+    }
+
+    /// Like `spawn`, with a provided handler to handle incoming notifications.
+    pub fn spawn_with_notification_handler<NH>(cmd: Command, notification_handler: NH, handle: &Handle) -> IoResult<Self>
+        where NH: server::Server + 'static
+    {
+        let (process, connection) = transport::launch(cmd)?;
+        let mut client = Self::with_notification_handler(connection, notification_handler, handle);
+        client.process = process;
+        Ok(client)
+    }
+
+    /// Like `spawn_with_notification_handler`, with the settings of `old`,
+    /// e.g. to replace a server which has died.
+    ///
+    /// The observer and any buffered notifications are moved over from
+    /// `old`, which is not expected to be used again.
+    pub(crate) fn respawn_like<NH>(old: &Self, cmd: Command, notification_handler: NH) -> IoResult<Self>
+        where NH: server::Server + 'static
+    {
+        let mut client = Self::spawn_with_notification_handler(cmd, notification_handler, &old.handle)?;
+        client.check_capabilities = old.check_capabilities;
+        client.queue_capacity = old.queue_capacity;
+        client.position_encodings = old.position_encodings.clone();
+        client.client_info = old.client_info.clone();
+        *client.observer.lock().unwrap() = old.observer.lock().unwrap().take();
+        *client.inbox.lock().unwrap() = old.inbox.lock().unwrap().take();
+        Ok(client)
+    }
+
+    /// Create a new `Client` talking to a server over the TCP `stream`,
+    /// framed with the default `LspCodec`.
+    pub fn from_tcp(stream: TcpStream, handle: &Handle) -> Self {
+        Self::new(stream.framed(LspCodec::default()), handle)
+    }
+
+    /// Create a new `Client` reading from and writing to the server over
+    /// separate streams, e.g. the stdout and stdin of a process launched
+    /// elsewhere.
+    pub fn from_stdio<R, W>(reader: R, writer: W, handle: &Handle) -> Self
+        where
+            R: AsyncRead + Send + 'static,
+            W: AsyncWrite + Send + 'static,
+    {
+        Self::new(transport::StdioConnection::new(reader, writer), handle)
+    }
+
+    /// The capabilities of the server, once it has been initialized.
+    pub fn capabilities(&self) -> Option<Ref<ServerCapabilities>> {
+        let capabilities = self.capabilities.borrow();
+        if capabilities.is_some() {
+            Some(Ref::map(capabilities, |c| c.as_ref().unwrap()))
+        } else {
+            None
+        }
+    }
+
+    /// Send `textDocument/didSave` for `document`, including its `text` if
+    /// the server asked for it, see `params::did_save`.
+    pub fn save_document(&mut self, document: TextDocumentIdentifier, text: &str) -> Result<(), LspError> {
+        let params = params::did_save(self.capabilities().as_ref().map(|c| &**c), document, text);
+        self.did_save_text_document(params)
+    }
+
+    /// Send `textDocument/didOpen` for the file at `path`, with its current
+    /// contents as version 1 of the document.
+    pub fn did_open_from_path(&mut self, path: &Path, language_id: &str) -> Result<(), LspError> {
+        let uri = uri::path_to_uri(path)?;
+        let text = fs::read_to_string(path).map_err(LspError::File)?;
+        self.did_open_text_document(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri, language_id.to_string(), 1, text),
+        })
+    }
+
+    /// When enabled, calls to methods which the server did not announce in its
+    /// capabilities fail with `LspError::Unsupported` instead of being sent.
+    ///
+    /// Nothing is checked before the server has been initialized.
+    pub fn check_capabilities(&mut self, enabled: bool) {
+        self.check_capabilities = enabled;
+    }
+
+    /// Consider the methods the server registered dynamically, as tracked
+    /// by a `handlers::RegistrationHandler`, as supported when checking
+    /// capabilities.
+    pub fn use_registrations(&mut self, registrations: Registrations) {
+        self.registrations = Some(registrations);
+    }
+
+    /// Set how many notifications may be queued by `notify` before they
+    /// have been written to the connection, see `DEFAULT_QUEUE_CAPACITY`.
+    pub fn set_queue_capacity(&mut self, capacity: usize) {
+        self.queue_capacity = capacity;
+    }
+
+    /// Offer the given position encodings to the server when initializing,
+    /// in order of preference.
+    ///
+    /// By default none are offered, so servers use UTF-16.
+    pub fn set_position_encodings(&mut self, encodings: Vec<PositionEncoding>) {
+        self.position_encodings = encodings;
+    }
+
+    /// Keep a copy of every notification from the server, as its method and
+    /// raw params, to be collected with `poll_notifications`.
+    ///
+    /// The notifications are still passed to the notification handler.
+    pub fn buffer_notifications(&mut self) {
+        let mut inbox = self.inbox.lock().unwrap();
+        if inbox.is_none() {
+            *inbox = Some(VecDeque::new());
+        }
+    }
+
+    /// Take the notifications received since the last call, for callers
+    /// which run the reactor themselves between requests.
+    ///
+    /// Always empty unless `buffer_notifications` was called.
+    pub fn poll_notifications(&mut self) -> Vec<(String, Option<Value>)> {
+        match *self.inbox.lock().unwrap() {
+            Some(ref mut inbox) => inbox.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Identify the client to the server as `info` when initializing.
+    pub fn set_client_info(&mut self, info: ClientInfo) {
+        self.client_info = Some(info);
+    }
+
+    /// The position encoding picked by the server, once it has been
+    /// initialized.
+    pub fn position_encoding(&self) -> PositionEncoding {
+        self.position_encoding.get()
+    }
+
+    /// Whether the connection to the server is still open.
+    ///
+    /// For a server launched with `spawn`, the connection closes when the
+    /// process exits.
+    pub fn is_alive(&self) -> bool {
+        self.alive.load(Ordering::SeqCst)
+    }
+
+    /// Change how much the server traces, see `handlers::TraceCollector`
+    /// for receiving the traces.
+    pub fn set_trace(&mut self, value: TraceOption) -> Result<(), LspError> {
+        self.notify::<SetTrace>(SetTraceParams { value })
+    }
+
+    /// Call `observer` with every message exchanged with the server, for
+    /// example to record a transcript of the session.
+    pub fn set_observer(&mut self, observer: Box<Fn(&TraceEvent) + Send>) {
+        *self.observer.lock().unwrap() = Some(observer);
+    }
+
+    /// The ids of requests which have been sent but not yet answered.
+    pub fn outstanding(&self) -> Vec<NumberOrString> {
+        self.outstanding.lock().unwrap().iter().filter_map(|&(ref id, _)| request_id(id)).collect()
+    }
+
+    /// Cancel every in-flight request for the method `Req`, for example to
+    /// abandon a `workspace/symbol` query which has been superseded.
+    ///
+    /// Returns the number of requests cancelled.
+    pub fn cancel_all<Req: Request>(&mut self) -> Result<usize, LspError> {
+        let ids: Vec<_> = self.outstanding.lock().unwrap().iter()
+            .filter(|&&(_, ref method)| method == Req::METHOD)
+            .filter_map(|&(ref id, _)| request_id(id))
+            .collect();
+        for id in &ids {
+            self.cancel(id.clone())?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Ask the server to abort work on the request with the given id.
+    pub fn cancel(&mut self, id: NumberOrString) -> Result<(), LspError> {
+        self.notify::<lsp_notification!("$/cancelRequest")>(CancelParams { id })
+    }
+}
+
+/// Whether a completion response is incomplete, in which case typing further
+/// should query the server again rather than filtering the items locally.
+pub fn is_incomplete(response: &CompletionResponse) -> bool {
+    match *response {
+        CompletionResponse::Array(_) => false,
+        CompletionResponse::List(ref list) => list.is_incomplete,
+    }
+}
+
+/// Flatten the result of `goto_definition` (and the other goto requests)
+/// into a list of locations.
+///
+/// Location links are reduced to their target selection range.
+pub fn into_locations(response: Option<GotoDefinitionResponse>) -> Vec<Location> {
+    match response {
+        None => Vec::new(),
+        Some(GotoDefinitionResponse::Scalar(location)) => vec![location],
+        Some(GotoDefinitionResponse::Array(locations)) => locations,
+        Some(GotoDefinitionResponse::Link(links)) => links.into_iter()
+            .map(|link| Location::new(link.target_uri, link.target_selection_range))
+            .collect(),
+    }
+}
+
+/// Flatten a `WorkspaceEdit` (e.g. the result of `rename`) into the text
+/// edits to make to each document.
+///
+/// Per the spec, `document_changes` is used in preference to `changes` when
+/// both are present, since servers may fill in both with the same edits.
+/// Edits to the same document are merged in order, and resource operations
+/// (creating, renaming or deleting files) are skipped.
+pub fn workspace_edits(edit: WorkspaceEdit) -> Vec<(Url, Vec<TextEdit>)> {
+    let mut documents: Vec<(Url, Vec<TextEdit>)> = Vec::new();
+    {
+        let mut add = |uri: Url, edits: Vec<TextEdit>| {
+            match documents.iter().position(|entry| entry.0 == uri) {
+                Some(i) => documents[i].1.extend(edits),
+                None => documents.push((uri, edits)),
+            }
+        };
+        match (edit.document_changes, edit.changes) {
+            (Some(DocumentChanges::Edits(edits)), _) => for edit in edits {
+                add(edit.text_document.uri, edit.edits);
+            },
+            (Some(DocumentChanges::Operations(operations)), _) => for operation in operations {
+                if let DocumentChangeOperation::Edit(edit) = operation {
+                    add(edit.text_document.uri, edit.edits);
+                }
+            },
+            (None, Some(changes)) => {
+                // Sort the map for a deterministic order
+                let mut changes: Vec<_> = changes.into_iter().collect();
+                changes.sort_by(|a, b| a.0.as_str().cmp(b.0.as_str()));
+                for (uri, edits) in changes {
+                    add(uri, edits);
+                }
+            },
+            (None, None) => (),
+        }
+    }
+    documents
+}
+
+/// The parameters of a `$/setTrace` notification.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SetTraceParams {
+    pub value: TraceOption,
+}
+
+/// The `$/setTrace` notification.
+pub enum SetTrace {}
+
+impl Notification for SetTrace {
+    type Params = SetTraceParams;
+    const METHOD: &'static str = "$/setTrace";
+}
+
+/// The future driving a client's connection, which resolves once the
+/// connection has closed.
+pub type Driver = Box<Future<Item=(), Error=IoError>>;
+
+/// A message exchanged with the server, as passed to the observer set with
+/// `Client::set_observer`.
+///
+/// `outgoing` is set for messages sent by the client.
+#[derive(Debug)]
+pub enum TraceEvent<'a> {
+    Request { outgoing: bool, id: &'a Value, method: &'a str, params: Option<&'a Value> },
+    Response { outgoing: bool, id: &'a Value, result: Result<&'a Value, &'a RpcError> },
+    Notification { outgoing: bool, method: &'a str, params: Option<&'a Value> },
+}
+
+impl<'a> TraceEvent<'a> {
+    fn from_message(outgoing: bool, msg: &'a Message) -> Option<Self> {
+        match *msg {
+            Message::Request(ref req) => Some(TraceEvent::Request {
+                outgoing,
+                id: &req.id,
+                method: &req.method,
+                params: req.params.as_ref(),
+            }),
+            Message::Response(ref resp) => Some(TraceEvent::Response {
+                outgoing,
+                id: &resp.id,
+                result: resp.result.as_ref(),
+            }),
+            Message::Notification(ref not) => Some(TraceEvent::Notification {
+                outgoing,
+                method: &not.method,
+                params: not.params.as_ref(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Observes every message exchanged with the server.
+type Observer = Arc<Mutex<Option<Box<Fn(&TraceEvent) + Send>>>>;
+
+/// Ids and methods of requests in flight, as seen on the wire.
+type Outstanding = Arc<Mutex<Vec<(Value, String)>>>;
+
+/// A message queued to be sent, with a sender to signal once it has been
+/// handed over to the connection.
+type Outgoing = (Message, Option<oneshot::Sender<()>>);
+
+/// Requests sent by the client, waiting for their response.
+type Waiting = Arc<Mutex<Vec<(Value, oneshot::Sender<Response>)>>>;
+
+/// Notifications received from the server, see `Client::poll_notifications`.
+///
+/// `None` unless notifications are being buffered.
+type Inbox = Arc<Mutex<Option<VecDeque<(String, Option<Value>)>>>>;
+
+/// Requests from the server whose handlers are still running, so that they
+/// can be cancelled with `$/cancelRequest`.
+type Inbound = Arc<Mutex<InboundRequests>>;
+
+#[derive(Default)]
+struct InboundRequests {
+    /// The id of the request last handed to jsonrpc, which passes it on to
+    /// the handler as soon as it is received.
+    current: Option<Value>,
+    handlers: Vec<(Value, oneshot::Sender<()>)>,
+}
+
+/// Wraps the handlers of requests from the server, to drop a handler's
+/// future when the server cancels the request.
+///
+/// The request is then answered with a `RequestCancelled` error.
+struct Cancellable<S> {
+    server: S,
+    inbound: Inbound,
+}
+
+impl<S: server::Server + 'static> server::Server for Cancellable<S> {
+    type Success = S::Success;
+    type RpcCallResult = Box<Future<Item=S::Success, Error=RpcError>>;
+    type NotificationResult = S::NotificationResult;
+
+    fn rpc(&self, ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::RpcCallResult> {
+        let id = self.inbound.lock().unwrap().current.take();
+        let handler = self.server.rpc(ctl, method, params)?.into_future();
+        let id = match id {
+            Some(id) => id,
+            None => return Some(Box::new(handler)),
+        };
+        let (sender, cancelled) = oneshot::channel();
+        self.inbound.lock().unwrap().handlers.push((id.clone(), sender));
+        let inbound = self.inbound.clone();
+        Some(Box::new(handler.select2(cancelled).then(move |res| {
+            inbound.lock().unwrap().handlers.retain(|&(ref pending, _)| pending != &id);
+            match res {
+                Ok(Either::A((success, _cancelled))) => Ok(success),
+                Err(Either::A((e, _cancelled))) => Err(e),
+                // The handler is dropped along with the select
+                Ok(Either::B(_)) | Err(Either::B(_)) => Err(RpcError {
+                    code: -32800,
+                    message: "Request cancelled".to_string(),
+                    data: None,
+                }),
+            }
+        })))
+    }
+
+    fn notification(&self, ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        self.server.notification(ctl, method, params)
+    }
+
+    fn initialized(&self, ctl: &ServerCtl) {
+        self.server.initialized(ctl)
+    }
+}
+
+/// Convert a request id to the form used by jsonrpc, see `request_id`.
+fn id_value(id: NumberOrString) -> Value {
+    match id {
+        NumberOrString::Number(n) => Value::from(n),
+        NumberOrString::String(s) => Value::String(s),
+    }
+}
+
+/// Convert a jsonrpc request id to the form used by the protocol.
+fn request_id(id: &Value) -> Option<NumberOrString> {
+    match *id {
+        Value::Number(ref n) => n.as_u64().map(NumberOrString::Number),
+        Value::String(ref s) => Some(NumberOrString::String(s.clone())),
+        _ => None,
+    }
+}
+
+/// Wraps a connection to keep track of which requests are still in flight.
+///
+/// The client's requests and notifications are sent from here directly, in
+/// the order they were queued, and the responses are kept from jsonrpc,
+/// which doesn't know about the requests.
+struct Tracked<C> {
+    inner: C,
+    outstanding: Outstanding,
+    waiting: Waiting,
+    outgoing: UnboundedReceiver<Outgoing>,
+    /// A queued message the connection wasn't ready for.
+    pending: Option<Outgoing>,
+    alive: Arc<AtomicBool>,
+    observer: Observer,
+    inbound: Inbound,
+    inbox: Inbox,
+}
+
+impl<C> Tracked<C> {
+    /// Mark the connection as closed. No responses are coming, so this fails
+    /// the requests waiting for one.
+    fn close(&self) {
+        self.alive.store(false, Ordering::SeqCst);
+        self.waiting.lock().unwrap().clear();
+        self.outstanding.lock().unwrap().clear();
+    }
+}
+
+impl<C> Drop for Tracked<C> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<C> Tracked<C>
+    where C: Sink<SinkItem = Message, SinkError = IoError>
+{
+    fn new(inner: C) -> (Self, UnboundedSender<Outgoing>) {
+        let (sender, outgoing) = mpsc::unbounded();
+        let tracked = Tracked {
+            inner,
+            outstanding: Outstanding::default(),
+            waiting: Waiting::default(),
+            outgoing,
+            pending: None,
+            alive: Arc::new(AtomicBool::new(true)),
+            observer: Observer::default(),
+            inbound: Inbound::default(),
+            inbox: Inbox::default(),
+        };
+        (tracked, sender)
+    }
+
+    fn observe(&self, outgoing: bool, msg: &Message) {
+        if let Some(ref observer) = *self.observer.lock().unwrap() {
+            if let Some(event) = TraceEvent::from_message(outgoing, msg) {
+                observer(&event);
+            }
+        }
+    }
+
+    /// Drop the handler of the request from the server cancelled by the
+    /// `$/cancelRequest` with `params`, if it is still running.
+    fn cancel_inbound(&self, params: &Option<Value>) {
+        let id = match params.as_ref().and_then(|params| params.get("id")) {
+            Some(id) => id,
+            None => return,
+        };
+        let mut inbound = self.inbound.lock().unwrap();
+        if let Some(idx) = inbound.handlers.iter().position(|&(ref pending, _)| pending == id) {
+            let _ = inbound.handlers.remove(idx).1.send(());
+        }
+    }
+
+    /// Hand over as many queued messages as the connection will take.
+    fn send_outgoing(&mut self) -> Result<(), IoError> {
+        loop {
+            let (msg, sent) = match self.pending.take() {
+                Some(outgoing) => outgoing,
+                None => match self.outgoing.poll() {
+                    Ok(Async::Ready(Some(outgoing))) => outgoing,
+                    _ => break,
+                },
+            };
+            match self.start_send(msg)? {
+                AsyncSink::Ready => {
+                    if let Some(sent) = sent {
+                        let _ = sent.send(());
+                    }
+                },
+                AsyncSink::NotReady(msg) => {
+                    self.pending = Some((msg, sent));
+                    break;
+                },
+            }
+        }
+        self.inner.poll_complete().map(|_| ())
+    }
+}
+
+impl<C> Stream for Tracked<C>
+    where C: Stream<Item = Parsed, Error = IoError>,
+          C: Sink<SinkItem = Message, SinkError = IoError>,
+{
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        // The incoming side is polled for as long as the connection is open,
+        // which makes it a convenient place to send queued messages from.
+        if let Err(e) = self.send_outgoing() {
+            self.close();
+            return Err(e);
+        }
+        loop {
+            let msg = match self.inner.poll() {
+                Ok(Async::Ready(msg)) => msg,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    self.close();
+                    return Err(e);
+                },
+            };
+            if let Some(Ok(ref msg)) = msg {
+                self.observe(false, msg);
+            }
+            if msg.is_none() {
+                self.close();
+            }
+            let resp = match msg {
+                Some(Ok(Message::Response(resp))) => resp,
+                Some(Ok(Message::Request(req))) => {
+                    self.inbound.lock().unwrap().current = Some(req.id.clone());
+                    return Ok(Async::Ready(Some(Ok(Message::Request(req)))));
+                },
+                Some(Ok(Message::Notification(not))) => {
+                    if not.method == "$/cancelRequest" {
+                        self.cancel_inbound(&not.params);
+                    }
+                    if let Some(ref mut inbox) = *self.inbox.lock().unwrap() {
+                        inbox.push_back((not.method.clone(), not.params.clone()));
+                    }
+                    return Ok(Async::Ready(Some(Ok(Message::Notification(not)))));
+                },
+                msg => return Ok(Async::Ready(msg)),
+            };
+            self.outstanding.lock().unwrap().retain(|&(ref id, _)| id != &resp.id);
+            let mut waiting = self.waiting.lock().unwrap();
+            match waiting.iter().position(|&(ref id, _)| id == &resp.id) {
+                Some(idx) => {
+                    let _ = waiting.remove(idx).1.send(resp);
+                },
+                None => return Ok(Async::Ready(Some(Ok(Message::Response(resp))))),
+            }
+        }
+    }
+}
+
+impl<C> Sink for Tracked<C>
+    where C: Sink<SinkItem = Message, SinkError = IoError>
+{
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, IoError> {
+        let request = match msg {
+            Message::Request(ref req) => Some((req.id.clone(), req.method.clone())),
+            _ => None,
+        };
+        let observed = match *self.observer.lock().unwrap() {
+            Some(_) => Some(msg.clone()),
+            None => None,
+        };
+        let res = self.inner.start_send(msg)?;
+        if res.is_ready() {
+            if let Some(request) = request {
+                self.outstanding.lock().unwrap().push(request);
+            }
+            if let Some(ref msg) = observed {
+                self.observe(true, msg);
+            }
         }
+        Ok(res)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        self.inner.poll_complete()
     }
 }
 
@@ -64,137 +824,1772 @@ impl Client {
     /// the reponse
     ///
     /// Use this as a generic way to make `LspClient` calls.
-    pub fn call<Req>(&mut self, params: Req::Params) -> Box<Future<Item=Req::Result, Error=IoError>>
+    ///
+    /// The jsonrpc layer only deals in `serde_json::Value`, for both the
+    /// parameters and the result, so the parameters are moved into a `Value`
+    /// exactly once, and the result is moved out of its `Value` as it is
+    /// deserialized. A result of the wrong shape is reported without the
+    /// raw result, which only `initialize` keeps.
+    pub fn call<Req>(&mut self, params: Req::Params) -> Box<Future<Item=Req::Result, Error=LspError>>
         where Req: Request,
               Req::Params: serde::Serialize,
               Req::Result: serde::de::DeserializeOwned + 'static,
     {
-        let params = match serde_json::to_value(params) {
-            Ok(res) => res,
-            Err(_e) => return Box::new(future::err(custom_err("Failed to serialize parameters"))),
-        };
-        let client = self.inner.take();
-        let client = match client {
-            None => return Box::new(future::err(custom_err("Tried to make a call on a poisoned client instance"))),
-            Some(c) => c,
-        };
-        // self.inner is a impl Future<Client>
-        let (client, fut) = match client.call(
-                Req::METHOD.to_string(),
-                Some(params),
-                None,
-        ).wait() {
-            Ok(res) => res,
-            Err(_e) => return Box::new(future::err(custom_err("Failed to send request"))),
-        };
-        self.inner = Some(client);
-        Box::new(fut.then(|resp| {
-            extract_response(resp)
-        }))
+        self.call_with_timeout::<Req>(params, None)
     }
 
-    /// Perfoms the main chunk of making a notification
-    pub fn notify<Not> (&mut self, params: Not::Params)
-        where Not: Notification,
-              Not::Params: serde::Serialize,
+    /// Like `call`, but also returns the id of the request, which can be used
+    /// to cancel it.
+    pub fn call_returning_id<Req>(&mut self, params: Req::Params) -> (NumberOrString, Box<Future<Item=Req::Result, Error=LspError>>)
+        where Req: Request,
+              Req::Params: serde::Serialize,
+              Req::Result: serde::de::DeserializeOwned + 'static,
     {
-        let params = match serde_json::to_value(params) {
-            Ok(res) => res,
-            Err(e) => {eprintln!("{}", e); return},
-        };
-        let client = self.inner.take();
-        let client: jsonrpc::Client = match client {
-            None => {eprintln!("Missing client"); return},
-            Some(c) => c,
-        };
-        // self.inner is a impl Future<Client>
-        self.inner = match client.notify(
-                Not::METHOD.to_string(),
-                Some(params),
-        ).wait() {
-            Ok(res) => Some(res),
-            Err(e) => {eprintln!("{}", e); return},
-        };
+        let id = self.next_request_id();
+        let call = self.call_with_id::<Req>(id.clone(), params);
+        (id, call)
     }
 
-}
-
+    fn next_request_id(&self) -> NumberOrString {
+        let next_id = self.next_id.get() + 1;
+        self.next_id.set(next_id);
+        NumberOrString::String(format!("lsp_client-{}", next_id))
+    }
 
-/// Extract/convert the result and map errors.
-fn extract_response<T>(resp: Result<Option<Response>, IoError>) -> Result<T, IoError>
-    where for<'de> T: serde::Deserialize<'de>
-{
-    let resp = resp.map_err(|e| custom_err(&format!("invalid response: {}", e)))?
-                    .ok_or(custom_err("expected a response value"))?
-                    .result.map_err(|_e| custom_err("invalid encoding"))?;
+    /// Like `call`, but sends the request with the given id.
+    ///
+    /// The id must not be shared with any other request in flight.
+    pub fn call_with_id<Req>(&mut self, id: NumberOrString, params: Req::Params) -> Box<Future<Item=Req::Result, Error=LspError>>
+        where Req: Request,
+              Req::Params: serde::Serialize,
+              Req::Result: serde::de::DeserializeOwned + 'static,
+    {
+        let params = match self.request_params::<Req>(params) {
+            Ok(params) => params,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        self.send_request(Req::METHOD, id, params)
+    }
 
-    serde_json::from_value(resp).map_err(|_e| custom_err("Failed to deserialize"))
-}
+    /// Check that the server supports `Req`, and convert its parameters.
+    fn request_params<Req>(&self, params: Req::Params) -> Result<Value, LspError>
+        where Req: Request,
+              Req::Params: serde::Serialize,
+    {
+        self.check_supported(Req::METHOD)?;
+        serde_json::to_value(params).map_err(LspError::Serialize)
+    }
 
+    /// Queue the request, and wait for the response with the same id.
+    fn send_request<R>(&mut self, method: &str, id: NumberOrString, params: Value) -> Box<Future<Item=R, Error=LspError>>
+        where R: serde::de::DeserializeOwned + 'static,
+    {
+        if !self.is_alive() {
+            return Box::new(future::err(LspError::ConnectionClosed));
+        }
+        let id = id_value(id);
+        let mut msg = Message::request(method.to_string(), Some(params));
+        if let Message::Request(ref mut req) = msg {
+            req.id = id.clone();
+        }
 
-macro_rules! lscall {
-    (@req $fn_name:ident, $name:tt) => {
-        fn $fn_name(&mut self, params: <lsp_request!($name) as Request>::Params) -> Box<Future<Item=<lsp_request!($name) as Request>::Result, Error=IoError>> {
-            self.call::<lsp_request!($name)>(params)
+        let (sender, receiver) = oneshot::channel();
+        self.waiting.lock().unwrap().push((id.clone(), sender));
+        if self.outgoing.unbounded_send((msg, None)).is_err() {
+            self.waiting.lock().unwrap().retain(|&(ref waiting, _)| waiting != &id);
+            return Box::new(future::err(LspError::ConnectionClosed));
         }
-    };
-    (@notify $fn_name:ident, $name:tt) => {
-        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), IoError> {
-            self.notify::<lsp_notification!($name)>(params);
-            Ok(())
+        // A dropped sender means the connection closed before the response
+        let alive = self.alive.clone();
+        let status = self.process.status.clone();
+        Box::new(receiver.then(|resp| extract_response(Ok(resp.ok())))
+            .map_err(move |e| closed_if_dead(&alive, &status, e)))
+    }
+
+    /// Fail with `LspError::Unsupported` if capabilities are being checked,
+    /// and the server did not announce support for `method`.
+    fn check_supported(&self, method: &str) -> Result<(), LspError> {
+        if self.check_capabilities {
+            if let Some(ref capabilities) = *self.capabilities.borrow() {
+                let registered = self.registrations.as_ref().map_or(false, |r| r.contains(method));
+                if !supports(capabilities, method) && !registered {
+                    return Err(LspError::Unsupported(method.to_string()));
+                }
+            }
         }
-    };
-}
+        Ok(())
+    }
 
-impl LspClient for Client {
-    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=IoError>> {
-        Box::new(self.call::<InitializeOptions>(params).map(|opt| {
-            match opt {
-                InitializeOptions::Result(r) => Ok(r),
-                InitializeOptions::Error(e) => Err(e),
+    /// Like `call`, but fails with `LspError::Timeout` if no response has
+    /// arrived within `timeout`.
+    pub fn call_with_timeout<Req>(&mut self, params: Req::Params, timeout: Option<Duration>) -> Box<Future<Item=Req::Result, Error=LspError>>
+        where Req: Request,
+              Req::Params: serde::Serialize,
+              Req::Result: serde::de::DeserializeOwned + 'static,
+    {
+        let params = match self.request_params::<Req>(params) {
+            Ok(params) => params,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        self.call_method(Req::METHOD, params, timeout)
+    }
+
+    /// Make a request for a method without a `Request` type, such as a
+    /// server-specific extension, returning the raw result.
+    pub fn call_raw(&mut self, method: &str, params: Value) -> Box<Future<Item=Value, Error=LspError>> {
+        self.call_method(method, params, None)
+    }
+
+    fn call_method<R>(&mut self, method: &str, params: Value, timeout: Option<Duration>) -> Box<Future<Item=R, Error=LspError>>
+        where R: serde::de::DeserializeOwned + 'static,
+    {
+        let id = self.next_request_id();
+        let response = self.send_request(method, id.clone(), params);
+
+        let timeout = match timeout {
+            None => return Box::new(response),
+            Some(t) => t,
+        };
+        let timer = match Timeout::new(timeout, &self.handle) {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(LspError::Transport(e))),
+        };
+        let mut client = self.clone();
+        Box::new(response.select2(timer).then(move |res| {
+            match res {
+                Ok(Either::A((resp, _timer))) => Ok(resp),
+                Ok(Either::B(((), _resp))) => {
+                    client.abandon(id);
+                    Err(LspError::Timeout)
+                },
+                Err(Either::A((e, _timer))) => Err(e),
+                Err(Either::B((e, _resp))) => Err(LspError::Transport(e)),
             }
         }))
     }
 
-    // lscall!(@notify $/cancelRequest, "$/cancelRequest");
-    // lscall!(@notify initialized, "initialized");
-    lscall!(@notify exit, "exit");
-    // lscall!(@notify window/showMessage, "window/showMessage");
-    // lscall!(@notify window/logMessage, "window/logMessage");
-    // lscall!(@notify telemetry/event, "telemetry/event");
-    lscall!(@notify did_open_text_document, "textDocument/didOpen");
-    lscall!(@notify did_change_text_document, "textDocument/didChange");
-    // lscall!(@notify textDocument/willSave, "textDocument/willSave");
-    lscall!(@notify did_save_text_document, "textDocument/didSave");
-    lscall!(@notify did_close_text_document, "textDocument/didClose");
+    /// Give up waiting for the response to the request `id`, and ask the
+    /// server to cancel it.
+    fn abandon(&mut self, id: NumberOrString) {
+        let value = id_value(id.clone());
+        self.waiting.lock().unwrap().retain(|&(ref waiting, _)| waiting != &value);
+        self.outstanding.lock().unwrap().retain(|&(ref outstanding, _)| outstanding != &value);
+        // Failing to send the cancellation is already logged by `notify`
+        let _ = self.cancel(id);
+    }
+
+    /// Perfoms the main chunk of making a notification
+    ///
+    /// The notification is sent in the background on the client's handle,
+    /// after the requests and notifications made before it.
+    /// Fails if the parameters can't be serialized, the connection has
+    /// already closed, or the queue of notifications waiting to be sent is
+    /// full (see `set_queue_capacity`).
+    pub fn notify<Not> (&mut self, params: Not::Params) -> Result<(), LspError>
+        where Not: Notification,
+              Not::Params: serde::Serialize,
+    {
+        let params = serde_json::to_value(params).map_err(|e| {
+            error!("failed to send {}: {}", Not::METHOD, e);
+            LspError::Serialize(e)
+        })?;
+        self.notify_raw(Not::METHOD, params)
+    }
+
+    /// Send a notification for a method without a `Notification` type, see
+    /// `call_raw` and `notify`.
+    pub fn notify_raw(&mut self, method: &str, params: Value) -> Result<(), LspError> {
+        if self.queued.get() >= self.queue_capacity {
+            return Err(LspError::QueueFull);
+        }
+        let sent = self.send_notification(method, params).map_err(|e| {
+            error!("failed to send {}: {}", method, e);
+            e
+        })?;
+        let queued = self.queued.clone();
+        queued.set(queued.get() + 1);
+        let method = method.to_string();
+        self.handle.spawn(sent.then(move |res| {
+            queued.set(queued.get() - 1);
+            res.map_err(|e| error!("failed to send {}: {}", method, e))
+        }));
+        Ok(())
+    }
+
+    /// Like `notify`, but returns a future which resolves once the
+    /// notification has been handed over to the connection.
+    pub(crate) fn notify_sent<Not> (&mut self, params: Not::Params) -> Box<Future<Item=(), Error=LspError>>
+        where Not: Notification,
+              Not::Params: serde::Serialize,
+    {
+        let sent = serde_json::to_value(params)
+            .map_err(LspError::Serialize)
+            .and_then(|params| self.send_notification(Not::METHOD, params));
+        match sent {
+            Ok(sent) => sent,
+            Err(e) => Box::new(future::err(e)),
+        }
+    }
+
+    /// Queue the notification behind the messages already queued.
+    ///
+    /// The future resolves once it has been handed over to the connection.
+    fn send_notification(&mut self, method: &str, params: Value) -> Result<Box<Future<Item=(), Error=LspError>>, LspError> {
+        if !self.is_alive() {
+            return Err(LspError::ConnectionClosed);
+        }
+        let (sender, receiver) = oneshot::channel();
+        let msg = Message::notification(method.to_string(), Some(params));
+        if self.outgoing.unbounded_send((msg, Some(sender))).is_err() {
+            return Err(LspError::ConnectionClosed);
+        }
+        // A dropped sender means the connection closed before it was sent
+        Ok(Box::new(receiver.map_err(|_| LspError::ConnectionClosed)))
+    }
+
+    /// Resolve all of `links` concurrently, see `documentLink/resolve`.
+    ///
+    /// The resolved links are returned in the same order.
+    pub fn resolve_all_links(&mut self, links: Vec<DocumentLink>) -> Box<Future<Item=Vec<DocumentLink>, Error=LspError>> {
+        let resolves: Vec<_> = links.into_iter().map(|link| self.document_link_resolve(link)).collect();
+        Box::new(future::join_all(resolves))
+    }
+
+    /// Resolve all of `items` concurrently, see `completionItem/resolve`.
+    ///
+    /// The resolved items are returned in the same order.
+    pub fn resolve_completion_items(&mut self, items: Vec<CompletionItem>) -> Box<Future<Item=Vec<CompletionItem>, Error=LspError>> {
+        let resolves: Vec<_> = items.into_iter().map(|item| self.resolve_completion_item(item)).collect();
+        Box::new(future::join_all(resolves))
+    }
+
+    /// Perform the shutdown sequence: send the `shutdown` request, and once it
+    /// has been answered send the `exit` notification.
+    ///
+    /// If the server was launched by the client, the future then waits for
+    /// the process to exit, and fails with `LspError::UnexpectedExit` unless
+    /// it exited with code 0 as the spec requires. Pair this with a timeout
+    /// to detect servers which hang instead of exiting.
+    pub fn shutdown_and_exit(mut self) -> Box<Future<Item=(), Error=LspError>> {
+        let child = self.process.child.lock().unwrap().take();
+        Box::new(self.shutdown(()).and_then(move |()| {
+            self.notify_sent::<lsp_notification!("exit")>(())
+        }).and_then(move |()| match child {
+            Some(child) => Either::A(child.map_err(LspError::Transport).and_then(|status| {
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(LspError::UnexpectedExit(status))
+                }
+            })),
+            None => Either::B(future::ok(())),
+        }))
+    }
+
+}
+
+
+/// The field of `ServerCapabilities` announcing support for a method, for
+/// methods which are optional.
+fn capability_for(method: &str) -> Option<&'static str> {
+    match method {
+        "textDocument/hover" => Some("hoverProvider"),
+        "textDocument/completion" | "completionItem/resolve" => Some("completionProvider"),
+        "textDocument/signatureHelp" => Some("signatureHelpProvider"),
+        "textDocument/definition" => Some("definitionProvider"),
+        "textDocument/typeDefinition" => Some("typeDefinitionProvider"),
+        "textDocument/implementation" => Some("implementationProvider"),
+        "textDocument/declaration" => Some("declarationProvider"),
+        "textDocument/references" => Some("referencesProvider"),
+        "textDocument/documentHighlight" => Some("documentHighlightProvider"),
+        "textDocument/documentSymbol" => Some("documentSymbolProvider"),
+        "workspace/symbol" | "workspaceSymbol/resolve" => Some("workspaceSymbolProvider"),
+        "workspace/executeCommand" => Some("executeCommandProvider"),
+        "textDocument/codeAction" | "codeAction/resolve" => Some("codeActionProvider"),
+        "textDocument/codeLens" | "codeLens/resolve" => Some("codeLensProvider"),
+        "textDocument/documentLink" | "documentLink/resolve" => Some("documentLinkProvider"),
+        "textDocument/formatting" => Some("documentFormattingProvider"),
+        "textDocument/rangeFormatting" => Some("documentRangeFormattingProvider"),
+        "textDocument/onTypeFormatting" => Some("documentOnTypeFormattingProvider"),
+        "textDocument/rename" | "textDocument/prepareRename" => Some("renameProvider"),
+        "textDocument/foldingRange" => Some("foldingRangeProvider"),
+        "textDocument/selectionRange" => Some("selectionRangeProvider"),
+        "textDocument/prepareCallHierarchy" | "callHierarchy/incomingCalls" |
+        "callHierarchy/outgoingCalls" => Some("callHierarchyProvider"),
+        "textDocument/semanticTokens/full" | "textDocument/semanticTokens/range" => Some("semanticTokensProvider"),
+        "textDocument/diagnostic" => Some("diagnosticProvider"),
+        "textDocument/inlayHint" | "inlayHint/resolve" => Some("inlayHintProvider"),
+        "textDocument/documentColor" | "textDocument/colorPresentation" => Some("colorProvider"),
+        _ => None,
+    }
+}
+
+/// Whether the server announced support for `method`.
+///
+/// The check is done on the serialized capabilities, so that it does not
+/// depend on the shape of each individual field.
+fn supports(capabilities: &ServerCapabilities, method: &str) -> bool {
+    let field = match capability_for(method) {
+        Some(field) => field,
+        None => return true,
+    };
+    match serde_json::to_value(capabilities) {
+        Ok(capabilities) => match capabilities.get(field) {
+            None | Some(&Value::Null) | Some(&Value::Bool(false)) => false,
+            Some(_) => true,
+        },
+        Err(_) => true,
+    }
+}
+
+/// Report a failed call as `LspError::ConnectionClosed` if it failed
+/// because the connection closed, or as `LspError::UnexpectedExit` if that
+/// was because the server process exited.
+fn closed_if_dead(alive: &AtomicBool, status: &Mutex<Option<ExitStatus>>, e: LspError) -> LspError {
+    match e {
+        LspError::Transport(_) | LspError::MissingResponse if !alive.load(Ordering::SeqCst) => {
+            match *status.lock().unwrap() {
+                Some(status) => LspError::UnexpectedExit(status),
+                None => LspError::ConnectionClosed,
+            }
+        },
+        e => e,
+    }
+}
+
+/// Extract/convert the result and map errors.
+///
+/// A `null` result is a valid answer to many requests, and decodes to `None`
+/// for results of type `Option<_>`. Only a missing response is an error.
+fn extract_response<T>(resp: Result<Option<Response>, IoError>) -> Result<T, LspError>
+    where for<'de> T: serde::Deserialize<'de>
+{
+    let resp = resp?
+                    .ok_or(LspError::MissingResponse)?
+                    .result.map_err(LspError::ServerError)?;
+
+    // Moving the result in lets strings and arrays be taken over rather
+    // than copied, so there is never a second copy of a large result
+    serde_json::from_value(resp).map_err(|e| LspError::Deserialize(e, None))
+}
+
+/// Deserialize a result, keeping the raw result in the error if it does not
+/// have the expected shape.
+///
+/// This copies everything out of `result`, so it is only used for small
+/// results worth reporting in full.
+fn deserialize_result<T>(result: Value) -> Result<T, LspError>
+    where for<'de> T: serde::Deserialize<'de>
+{
+    let deserialized = T::deserialize(&result);
+    deserialized.map_err(|e| LspError::Deserialize(e, Some(result)))
+}
+
+
+macro_rules! lscall {
+    (@req $fn_name:ident, $name:tt) => {
+        fn $fn_name(&mut self, params: <request!($name) as Request>::Params) -> Box<Future<Item=<request!($name) as Request>::Result, Error=LspError>> {
+            self.call::<request!($name)>(params)
+        }
+    };
+    // Formatting requests, whose options are checked before sending
+    (@formatting $fn_name:ident, $name:tt) => {
+        fn $fn_name(&mut self, params: <request!($name) as Request>::Params) -> Box<Future<Item=<request!($name) as Request>::Result, Error=LspError>> {
+            if let Err(e) = check_formatting_options(&params.options) {
+                return Box::new(future::err(e));
+            }
+            self.call::<request!($name)>(params)
+        }
+    };
+    (@notify $fn_name:ident, $name:tt) => {
+        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), LspError> {
+            self.notify::<lsp_notification!($name)>(params)
+        }
+    };
+}
+
+impl LspClient for Client {
+    type Error = LspError;
+
+    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=LspError>> {
+        let mut params = match serde_json::to_value(params) {
+            Ok(params) => params,
+            Err(e) => return Box::new(future::err(LspError::Serialize(e))),
+        };
+        // Offered as `capabilities.general.positionEncodings`, which is not
+        // modelled by `ClientCapabilities`
+        if !self.position_encodings.is_empty() {
+            let encodings = serde_json::to_value(&self.position_encodings).expect("encodings serialize");
+            if let Some(capabilities) = params.get_mut("capabilities").and_then(Value::as_object_mut) {
+                let general = capabilities.entry("general").or_insert_with(|| Value::Object(Default::default()));
+                if let Some(general) = general.as_object_mut() {
+                    general.insert("positionEncodings".to_string(), encodings);
+                }
+            }
+        }
+        if let Some(ref info) = self.client_info {
+            if let Some(params) = params.as_object_mut() {
+                params.insert("clientInfo".to_string(), serde_json::to_value(info).expect("client info serializes"));
+            }
+        }
+
+        let capabilities = self.capabilities.clone();
+        let position_encoding = self.position_encoding.clone();
+        Box::new(self.call_method::<Value>("initialize", params, None).and_then(move |result| {
+            let encoding = match result.pointer("/capabilities/positionEncoding") {
+                None => PositionEncoding::Utf16,
+                Some(encoding) => serde_json::from_value(encoding.clone()).unwrap_or_else(|_e| {
+                    warn!("unknown position encoding {}, assuming utf-16", encoding);
+                    PositionEncoding::Utf16
+                }),
+            };
+            let result = deserialize_result::<InitializeOptions>(result)?.into_result();
+            if let Ok(ref r) = result {
+                *capabilities.borrow_mut() = Some(r.capabilities.clone());
+                position_encoding.set(encoding);
+            }
+            Ok(result)
+        }))
+    }
+
+    lscall!(@notify cancel_request, "$/cancelRequest");
+    lscall!(@notify initialized, "initialized");
+    lscall!(@notify exit, "exit");
+    // lscall!(@notify window/showMessage, "window/showMessage");
+    // lscall!(@notify window/logMessage, "window/logMessage");
+    // lscall!(@notify telemetry/event, "telemetry/event");
+    lscall!(@notify did_open_text_document, "textDocument/didOpen");
+    lscall!(@notify did_change_text_document, "textDocument/didChange");
+    // lscall!(@notify textDocument/willSave, "textDocument/willSave");
+    lscall!(@notify did_save_text_document, "textDocument/didSave");
+    lscall!(@notify did_close_text_document, "textDocument/didClose");
     // lscall!(@notify textDocument/publishDiagnostics, "textDocument/publishDiagnostics");
     lscall!(@notify did_change_configuration, "workspace/didChangeConfiguration");
     lscall!(@notify did_change_watched_files, "workspace/didChangeWatchedFiles");
+    lscall!(@notify did_change_workspace_folders, "workspace/didChangeWorkspaceFolders");
 
     // lscall!(@req initialize, "initialize");
     lscall!(@req shutdown, "shutdown");
-    // lscall!(@req window/showMessageRequest, "window/showMessageRequest");
-    // lscall!(@req client/registerCapability, "client/registerCapability");
-    // lscall!(@req client/unregisterCapability, "client/unregisterCapability");
+    // window/showMessageRequest is sent by the server, see `handlers::ShowMessageRequestHandler`
+    // client/registerCapability and client/unregisterCapability are sent by the server, see `handlers::RegistrationHandler`
     lscall!(@req workspace_symbols, "workspace/symbol");
-    // lscall!(@req workspace/executeCommand, "workspace/executeCommand");
-    // lscall!(@req textDocument/willSaveWaitUntil, "textDocument/willSaveWaitUntil");
+    lscall!(@req resolve_workspace_symbol, "workspaceSymbol/resolve");
+    lscall!(@req execute_command, "workspace/executeCommand");
+    lscall!(@req will_save_wait_until, "textDocument/willSaveWaitUntil");
     lscall!(@req completion, "textDocument/completion");
     lscall!(@req resolve_completion_item, "completionItem/resolve");
     lscall!(@req hover, "textDocument/hover");
     lscall!(@req signature_help, "textDocument/signatureHelp");
     lscall!(@req goto_definition, "textDocument/definition");
+    lscall!(@req goto_type_definition, "textDocument/typeDefinition");
+    lscall!(@req goto_implementation, "textDocument/implementation");
+    lscall!(@req goto_declaration, "textDocument/declaration");
     lscall!(@req references, "textDocument/references");
     lscall!(@req document_highlight, "textDocument/documentHighlight");
     lscall!(@req document_symbols, "textDocument/documentSymbol");
     lscall!(@req code_action, "textDocument/codeAction");
+    lscall!(@req resolve_code_action, "codeAction/resolve");
     lscall!(@req code_lens, "textDocument/codeLens");
     lscall!(@req code_lens_resolve, "codeLens/resolve");
     lscall!(@req document_link, "textDocument/documentLink");
     lscall!(@req document_link_resolve, "documentLink/resolve");
+    lscall!(@req folding_range, "textDocument/foldingRange");
+    lscall!(@req selection_range, "textDocument/selectionRange");
+    lscall!(@req document_color, "textDocument/documentColor");
+    lscall!(@req color_presentation, "textDocument/colorPresentation");
+    lscall!(@req prepare_call_hierarchy, "textDocument/prepareCallHierarchy");
+    lscall!(@req incoming_calls, "callHierarchy/incomingCalls");
+    lscall!(@req outgoing_calls, "callHierarchy/outgoingCalls");
+    lscall!(@req semantic_tokens_full, "textDocument/semanticTokens/full");
+    lscall!(@req semantic_tokens_range, "textDocument/semanticTokens/range");
+    lscall!(@req document_diagnostics, "textDocument/diagnostic");
+    lscall!(@req inlay_hint, "textDocument/inlayHint");
+    lscall!(@req inlay_hint_resolve, "inlayHint/resolve");
     // lscall!(@req textDocument/applyEdit, "textDocument/applyEdit");
-    lscall!(@req range_formatting, "textDocument/rangeFormatting");
-    lscall!(@req on_type_formatting, "textDocument/onTypeFormatting");
-    lscall!(@req formatting, "textDocument/formatting");
+    lscall!(@formatting range_formatting, "textDocument/rangeFormatting");
+    lscall!(@formatting on_type_formatting, "textDocument/onTypeFormatting");
+    lscall!(@formatting formatting, "textDocument/formatting");
     lscall!(@req rename, "textDocument/rename");
+    lscall!(@req prepare_rename, "textDocument/prepareRename");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+    use tokio_core::reactor::Core;
+
+    fn position_params() -> TextDocumentPositionParams {
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap()),
+            position: Position::new(0, 0),
+        }
+    }
+
+    #[test]
+    fn test_call_inside_reactor() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(serde_json::Value::Null));
+        let mut client = Client::new(pipe, &handle);
+
+        let result = core.run(future::lazy(move || client.shutdown(())));
+        assert!(result.is_ok());
+        match received.borrow()[0] {
+            Message::Request(ref req) => assert_eq!(req.method, "shutdown"),
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let _received = test_util::serve(remote, &handle, |_req| Some(json!({"capabilities": {"hoverProvider": true}})));
+        let mut client = Client::new(pipe, &handle);
+
+        assert!(client.capabilities().is_none());
+        core.run(client.initialize(test_util::init_params())).unwrap().unwrap();
+        assert_eq!(client.capabilities().unwrap().hover_provider, Some(true));
+    }
+
+    #[test]
+    fn test_save_document() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+        *client.capabilities.borrow_mut() = Some(serde_json::from_value(json!({
+            "textDocumentSync": {"save": {"includeText": true}},
+        })).unwrap());
+
+        let document = TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap());
+        client.save_document(document, "fn main() {}").unwrap();
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => {
+                assert_eq!(not.method, "textDocument/didSave");
+                assert_eq!(not.params.as_ref().unwrap()["text"], json!("fn main() {}"));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_did_open_from_path() {
+        use std::env;
+        use std::io::Write;
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        let path = env::temp_dir().join(format!("tokio_lsp_did_open_{}.rs", ::std::process::id()));
+        fs::File::create(&path).unwrap().write_all(b"fn main() {}").unwrap();
+        let res = client.did_open_from_path(&path, "rust");
+        fs::remove_file(&path).unwrap();
+        res.unwrap();
+
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => {
+                let params = not.params.as_ref().unwrap();
+                assert_eq!(not.method, "textDocument/didOpen");
+                assert_eq!(params["textDocument"]["languageId"], json!("rust"));
+                assert_eq!(params["textDocument"]["text"], json!("fn main() {}"));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+
+        match client.did_open_from_path(&path, "rust") {
+            Err(LspError::File(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_position_encoding() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(json!({"capabilities": {"positionEncoding": "utf-8"}})));
+        let mut client = Client::new(pipe, &handle);
+        client.set_position_encodings(vec![PositionEncoding::Utf8, PositionEncoding::Utf16]);
+
+        assert_eq!(client.position_encoding(), PositionEncoding::Utf16);
+        core.run(client.initialize(test_util::init_params())).unwrap().unwrap();
+        assert_eq!(client.position_encoding(), PositionEncoding::Utf8);
+        match received.borrow()[0] {
+            Message::Request(ref req) => {
+                let params = req.params.as_ref().unwrap();
+                assert_eq!(params["capabilities"]["general"]["positionEncodings"], json!(["utf-8", "utf-16"]));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_client_info() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(json!({"capabilities": {}})));
+        let mut client = Client::new(pipe, &handle);
+        client.set_client_info(ClientInfo::new("tokio_lsp", Some("0.1.0")));
+
+        core.run(client.initialize(test_util::init_params())).unwrap().unwrap();
+        match received.borrow()[0] {
+            Message::Request(ref req) => {
+                let params = req.params.as_ref().unwrap();
+                assert_eq!(params["clientInfo"], json!({"name": "tokio_lsp", "version": "0.1.0"}));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+        assert_eq!(serde_json::to_value(ClientInfo::new("tokio_lsp", None)).unwrap(), json!({"name": "tokio_lsp"}));
+    }
+
+    #[test]
+    fn test_connect_and_initialize() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let attempts = Rc::new(Cell::new(0));
+        let counter = attempts.clone();
+        let server_handle = handle.clone();
+        // The server isn't listening yet on the first attempt
+        let connect = move || -> Box<Future<Item=test_util::Pipe, Error=IoError>> {
+            counter.set(counter.get() + 1);
+            if counter.get() == 1 {
+                return Box::new(future::err(custom_err("connection refused")));
+            }
+            let (pipe, remote) = test_util::pipe();
+            test_util::serve(remote, &server_handle, |_req| Some(json!({"capabilities": {}})));
+            Box::new(future::ok(pipe))
+        };
+        let policy = ReconnectPolicy { max_retries: 3, backoff: Duration::from_millis(10) };
+
+        let init = Client::connect_and_initialize(connect, test_util::init_params(), policy, &handle);
+        let (client, resp) = core.run(init).unwrap();
+        assert!(resp.is_ok());
+        assert!(client.capabilities().is_some());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn test_malformed_initialize_result() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let _received = test_util::serve(remote, &handle, |_req| Some(json!({"capabilities": 42})));
+        let mut client = Client::new(pipe, &handle);
+
+        match core.run(client.initialize(test_util::init_params())) {
+            Err(e @ LspError::Deserialize(..)) => {
+                assert!(e.to_string().contains(r#"{"capabilities":42}"#));
+                match e {
+                    LspError::Deserialize(_, result) => assert_eq!(result, Some(json!({"capabilities": 42}))),
+                    _ => unreachable!(),
+                }
+            },
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(client.capabilities().is_none());
+    }
+
+    #[test]
+    fn test_check_capabilities() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let _received = test_util::serve(remote, &handle, |_req| Some(Value::Null));
+        let mut client = Client::new(pipe, &handle);
+        *client.capabilities.borrow_mut() = Some(serde_json::from_value(json!({"hoverProvider": true})).unwrap());
+        client.check_capabilities(true);
+
+        let position = position_params();
+        assert_eq!(core.run(client.hover(position.clone())).unwrap(), None);
+
+        let rename = RenameParams {
+            text_document: position.text_document,
+            position: position.position,
+            new_name: "renamed".to_string(),
+        };
+        match core.run(client.rename(rename)) {
+            Err(LspError::Unsupported(ref method)) => assert_eq!(method, "textDocument/rename"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_concurrent_calls() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(Value::Null));
+        let mut client = Client::new(pipe, &handle);
+
+        let result = core.run(future::lazy(move || {
+            let first = client.shutdown(());
+            let second = client.shutdown(());
+            let third = client.shutdown(());
+            first.join3(second, third)
+        }));
+        assert!(result.is_ok());
+        assert_eq!(received.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_call_timeout() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        // A server which never answers
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        let call = client.call_with_timeout::<lsp_request!("shutdown")>((), Some(Duration::from_millis(50)));
+        match core.run(call) {
+            Err(LspError::Timeout) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(client.outstanding().is_empty());
+
+        test_util::settle(&mut core);
+        let received = received.borrow();
+        let id = match received[0] {
+            Message::Request(ref req) => req.id.clone(),
+            ref m => panic!("unexpected message: {:?}", m),
+        };
+        match received[1] {
+            Message::Notification(ref not) => {
+                assert_eq!(not.method, "$/cancelRequest");
+                assert_eq!(not.params, Some(json!({"id": id})));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_server_error() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let to_client = remote.to_client;
+        handle.spawn(remote.from_client.for_each(move |msg| {
+            if let Message::Request(ref req) = msg {
+                let error = RpcError {
+                    code: -32601,
+                    message: "Method not found".to_string(),
+                    data: Some(json!({"method": req.method})),
+                };
+                let _ = to_client.unbounded_send(Ok(req.error(error)));
+            }
+            Ok(())
+        }));
+        let mut client = Client::new(pipe, &handle);
+
+        match core.run(future::lazy(move || client.shutdown(()))) {
+            Err(LspError::ServerError(e)) => {
+                assert_eq!(e.code, -32601);
+                assert_eq!(e.message, "Method not found");
+                assert_eq!(e.data, Some(json!({"method": "shutdown"})));
+            },
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        client.cancel(NumberOrString::Number(3)).unwrap();
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => {
+                assert_eq!(not.method, "$/cancelRequest");
+                assert_eq!(not.params, Some(json!({"id": 3})));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_set_trace() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        client.set_trace(TraceOption::Verbose).unwrap();
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => {
+                assert_eq!(not.method, "$/setTrace");
+                assert_eq!(not.params, Some(json!({"value": "verbose"})));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_is_incomplete() {
+        let items = json!([{"label": "main"}]);
+        let array: CompletionResponse = serde_json::from_value(items.clone()).unwrap();
+        assert!(!is_incomplete(&array));
+        let complete: CompletionResponse = serde_json::from_value(json!({"isIncomplete": false, "items": items})).unwrap();
+        assert!(!is_incomplete(&complete));
+        let incomplete: CompletionResponse = serde_json::from_value(json!({"isIncomplete": true, "items": items})).unwrap();
+        assert!(is_incomplete(&incomplete));
+    }
+
+    #[test]
+    fn test_formatting_zero_tab_size() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(json!([])));
+        let mut client = Client::new(pipe, &handle);
+
+        let params = |tab_size| serde_json::from_value(json!({
+            "textDocument": {"uri": "file:///tmp/main.rs"},
+            "options": {"tabSize": tab_size, "insertSpaces": true},
+        })).unwrap();
+        match core.run(client.formatting(params(0))) {
+            Err(LspError::InvalidParams(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(received.borrow().is_empty());
+        assert!(core.run(client.formatting(params(4))).is_ok());
+    }
+
+    #[test]
+    fn test_did_change_workspace_folders() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        let event = json!({
+            "event": {
+                "added": [{"uri": "file:///tmp/other", "name": "other"}],
+                "removed": [],
+            },
+        });
+        client.did_change_workspace_folders(serde_json::from_value(event.clone()).unwrap()).unwrap();
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => {
+                assert_eq!(not.method, "workspace/didChangeWorkspaceFolders");
+                assert_eq!(not.params, Some(event));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_into_locations() {
+        let location = json!({
+            "uri": "file:///tmp/main.rs",
+            "range": {"start": {"line": 1, "character": 3}, "end": {"line": 1, "character": 7}},
+        });
+        let expected: Location = serde_json::from_value(location.clone()).unwrap();
+
+        let (resp, _req) = test_util::roundtrip(location.clone(), |c| c.goto_definition(position_params()));
+        assert_eq!(into_locations(resp), vec![expected.clone()]);
+        let (resp, _req) = test_util::roundtrip(json!([location, location]), |c| c.goto_definition(position_params()));
+        assert_eq!(into_locations(resp), vec![expected.clone(), expected]);
+        let (resp, _req) = test_util::roundtrip(Value::Null, |c| c.goto_definition(position_params()));
+        assert!(into_locations(resp).is_empty());
+    }
+
+    #[test]
+    fn test_workspace_edits_changes() {
+        let edit: WorkspaceEdit = serde_json::from_value(json!({
+            "changes": {
+                "file:///tmp/main.rs": [
+                    {"range": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 7}}, "newText": "run"},
+                ],
+                "file:///tmp/lib.rs": [],
+            },
+        })).unwrap();
+        let edits = workspace_edits(edit);
+        let uris: Vec<_> = edits.iter().map(|&(ref uri, _)| uri.as_str()).collect();
+        assert_eq!(uris, vec!["file:///tmp/lib.rs", "file:///tmp/main.rs"]);
+        assert_eq!(edits[1].1[0].new_text, "run");
+    }
+
+    #[test]
+    fn test_workspace_edits_document_changes() {
+        let text_edit = json!({"range": {"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 7}}, "newText": "run"});
+        let edit: WorkspaceEdit = serde_json::from_value(json!({
+            "changes": {"file:///tmp/ignored.rs": [text_edit]},
+            "documentChanges": [
+                {"textDocument": {"uri": "file:///tmp/main.rs", "version": 1}, "edits": [text_edit]},
+                {"textDocument": {"uri": "file:///tmp/lib.rs", "version": 1}, "edits": [text_edit]},
+                {"textDocument": {"uri": "file:///tmp/main.rs", "version": 1}, "edits": [text_edit]},
+            ],
+        })).unwrap();
+        let edits = workspace_edits(edit);
+        let uris: Vec<_> = edits.iter().map(|&(ref uri, _)| uri.as_str()).collect();
+        assert_eq!(uris, vec!["file:///tmp/main.rs", "file:///tmp/lib.rs"]);
+        assert_eq!(edits[0].1.len(), 2);
+        assert_eq!(edits[1].1.len(), 1);
+    }
+
+    #[test]
+    fn test_notify_failure_logged() {
+        use log::{self, Log, Metadata, Record};
+        use std::collections::HashMap;
+
+        thread_local!(static LOGGED: RefCell<Vec<String>> = RefCell::new(Vec::new()));
+        struct Capture;
+        impl Log for Capture {
+            fn enabled(&self, _metadata: &Metadata) -> bool { true }
+            fn log(&self, record: &Record) {
+                LOGGED.with(|logged| logged.borrow_mut().push(format!("{}", record.args())));
+            }
+            fn flush(&self) {}
+        }
+        static CAPTURE: Capture = Capture;
+        // Another test may have installed it already
+        let _ = log::set_logger(&CAPTURE);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        // Maps with non-string keys can't be serialized
+        enum Broken {}
+        impl Notification for Broken {
+            type Params = HashMap<(u32, u32), u32>;
+            const METHOD: &'static str = "test/broken";
+        }
+
+        let core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let _received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        assert!(client.notify::<Broken>(vec![((1, 2), 3)].into_iter().collect()).is_err());
+        LOGGED.with(|logged| {
+            assert!(logged.borrow().iter().any(|msg| msg.starts_with("failed to send test/broken")));
+        });
+    }
+
+    #[test]
+    fn test_observer() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let _received = test_util::serve(remote, &handle, |_req| Some(Value::Null));
+        let mut client = Client::new(pipe, &handle);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let log = events.clone();
+        client.set_observer(Box::new(move |event: &TraceEvent| {
+            let entry = match *event {
+                TraceEvent::Request { outgoing, id, method, .. } => (outgoing, id.clone(), method.to_string()),
+                TraceEvent::Response { outgoing, id, .. } => (outgoing, id.clone(), "response".to_string()),
+                TraceEvent::Notification { outgoing, method, .. } => (outgoing, Value::Null, method.to_string()),
+            };
+            log.lock().unwrap().push(entry);
+        }));
+
+        core.run(client.shutdown(())).unwrap();
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!((events[0].0, &events[0].2[..]), (true, "shutdown"));
+        assert_eq!((events[1].0, &events[1].2[..]), (false, "response"));
+        assert_eq!(events[0].1, events[1].1);
+    }
+
+    #[test]
+    fn test_notify_closed() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let mut client = Client::new(pipe, &handle);
+
+        drop(remote);
+        test_util::settle(&mut core);
+        match client.exit(()) {
+            Err(LspError::ConnectionClosed) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_resolve_all_links() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        // Resolve each link to a target named after the line it is on
+        let _received = test_util::serve(remote, &handle, |req| {
+            let mut link = req.params.clone().unwrap();
+            let line = link["range"]["start"]["line"].as_u64().unwrap();
+            link["target"] = json!(format!("file:///tmp/{}.rs", line));
+            Some(link)
+        });
+        let mut client = Client::new(pipe, &handle);
+
+        let links = (0..2).map(|line| serde_json::from_value(json!({
+            "range": {"start": {"line": line, "character": 0}, "end": {"line": line, "character": 4}},
+        })).unwrap()).collect();
+        let resolved = core.run(client.resolve_all_links(links)).unwrap();
+        let targets: Vec<_> = resolved.iter().map(|link| link.target.as_ref().unwrap().as_str()).collect();
+        assert_eq!(targets, vec!["file:///tmp/0.rs", "file:///tmp/1.rs"]);
+    }
+
+    #[test]
+    fn test_resolve_completion_items() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |req| {
+            let mut item = req.params.clone().unwrap();
+            item["detail"] = json!(format!("fn {}()", item["label"].as_str().unwrap()));
+            Some(item)
+        });
+        let mut client = Client::new(pipe, &handle);
+
+        let items = vec!["main", "run", "stop"].into_iter()
+            .map(|label| serde_json::from_value(json!({"label": label})).unwrap())
+            .collect();
+        let resolved = core.run(client.resolve_completion_items(items)).unwrap();
+        let details: Vec<_> = resolved.iter().map(|item| item.detail.as_ref().unwrap().as_str()).collect();
+        assert_eq!(details, vec!["fn main()", "fn run()", "fn stop()"]);
+        assert_eq!(received.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_outstanding() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let _received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        handle.spawn(client.shutdown(()).then(|_| Ok(())));
+        test_util::settle(&mut core);
+        assert_eq!(client.outstanding().len(), 1);
+    }
+
+    #[test]
+    fn test_large_result() {
+        enum Large {}
+        impl Request for Large {
+            type Params = Vec<String>;
+            type Result = Vec<String>;
+            const METHOD: &'static str = "test/large";
+        }
+
+        let items: Vec<String> = (0..10_000).map(|i| format!("item {}", i)).collect();
+        let params = items.clone();
+        let (resp, req) = test_util::roundtrip(json!(items), move |c| c.call::<Large>(params));
+        assert_eq!(req.params, Some(json!(items)));
+        assert_eq!(resp, items);
+    }
+
+    #[test]
+    fn test_result_converted_in_place() {
+        let label = "x".repeat(8192);
+        let items: Vec<_> = (0..1000).map(|_i| json!({"label": label})).collect();
+        let resp = match Message::request("textDocument/completion".to_string(), None) {
+            Message::Request(req) => req.reply(json!(items)),
+            m => panic!("unexpected message: {:?}", m),
+        };
+        let resp = match resp {
+            Message::Response(resp) => resp,
+            m => panic!("unexpected message: {:?}", m),
+        };
+
+        let (completions, peak) = test_util::peak_allocated(move || {
+            extract_response::<Vec<CompletionItem>>(Ok(Some(resp))).unwrap()
+        });
+        assert_eq!(completions.len(), 1000);
+        assert!(completions.iter().all(|item| item.label == label));
+        // The labels are moved over rather than copied
+        assert!(peak < items.len() * label.len() / 2, "peak allocation {}", peak);
+    }
+
+    #[test]
+    fn test_malformed_result() {
+        let (pipe, remote) = test_util::pipe();
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let _received = test_util::serve(remote, &handle, |_req| Some(json!({"contents": 42})));
+        let mut client = Client::new(pipe, &handle);
+
+        match core.run(client.hover(position_params())) {
+            Err(LspError::Deserialize(_, None)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_workspace_symbols_peak_memory() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let mut client = Client::new(pipe, &handle);
+
+        let call = client.workspace_symbols(WorkspaceSymbolParams { query: String::new() });
+        let (req, _from_client) = test_util::recv(&mut core, remote.from_client);
+        let req = match req {
+            Message::Request(req) => req,
+            m => panic!("unexpected message: {:?}", m),
+        };
+        let (resp, size) = test_util::peak_allocated(|| {
+            let symbols: Vec<_> = (0..2000).map(|i| json!({
+                "name": format!("{}{}", "symbol".repeat(200), i),
+                "kind": 12,
+                "location": {
+                    "uri": format!("file:///tmp/{}/lib.rs", "module/".repeat(20)),
+                    "range": {"start": {"line": i, "character": 0}, "end": {"line": i, "character": 10}},
+                },
+            })).collect();
+            req.reply(Value::Array(symbols))
+        });
+        remote.to_client.unbounded_send(Ok(resp)).unwrap();
+
+        // `from_value` moves the strings out of the parsed `Value` rather
+        // than copying them, so converting it costs much less than the
+        // result itself
+        let (symbols, peak) = test_util::peak_allocated(|| core.run(call).unwrap());
+        assert_eq!(symbols.unwrap().len(), 2000);
+        assert!(peak < size / 2, "peak allocation {} for a result of {}", peak, size);
+    }
+
+    #[test]
+    fn test_cancel_all() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        let query = WorkspaceSymbolParams { query: "Client".to_string() };
+        handle.spawn(client.workspace_symbols(query).then(|_| Ok(())));
+        handle.spawn(client.hover(position_params()).then(|_| Ok(())));
+        test_util::settle(&mut core);
+
+        assert_eq!(client.cancel_all::<lsp_request!("workspace/symbol")>().unwrap(), 1);
+        test_util::settle(&mut core);
+        let received = received.borrow();
+        let symbol_id = match received[0] {
+            Message::Request(ref req) => {
+                assert_eq!(req.method, "workspace/symbol");
+                req.id.clone()
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        };
+        match received[2] {
+            Message::Notification(ref not) => {
+                assert_eq!(not.method, "$/cancelRequest");
+                assert_eq!(not.params, Some(json!({"id": symbol_id})));
+            },
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_call_with_id() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(Value::Null));
+        let mut client = Client::new(pipe, &handle);
+
+        let call = client.call_with_id::<lsp_request!("shutdown")>(NumberOrString::String("shutdown-1".to_string()), ());
+        core.run(call).unwrap();
+        match received.borrow()[0] {
+            Message::Request(ref req) => assert_eq!(req.id, json!("shutdown-1")),
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+
+        let (id, call) = client.call_returning_id::<lsp_request!("shutdown")>(());
+        core.run(call).unwrap();
+        match received.borrow()[1] {
+            Message::Request(ref req) => assert_eq!(request_id(&req.id), Some(id)),
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+        assert!(client.outstanding().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_code_action() {
+        let action: CodeAction = serde_json::from_value(json!({"title": "Add missing import"})).unwrap();
+        let resolved = json!({
+            "title": "Add missing import",
+            "edit": {"changes": {"file:///tmp/main.rs": []}},
+        });
+        let (resp, req) = test_util::roundtrip(resolved, move |c| c.resolve_code_action(action));
+        assert_eq!(req.method, "codeAction/resolve");
+        assert_eq!(req.params, Some(json!({"title": "Add missing import"})));
+        assert!(resp.edit.is_some());
+    }
+
+    #[test]
+    fn test_prepare_rename() {
+        let range = Range::new(Position::new(0, 3), Position::new(0, 7));
+        let range_json = json!({"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 7}});
+
+        let (resp, req) = test_util::roundtrip(range_json.clone(), |c| c.prepare_rename(position_params()));
+        assert_eq!(req.method, "textDocument/prepareRename");
+        assert_eq!(resp, Some(PrepareRenameResponse::Range(range)));
+
+        let (resp, _req) = test_util::roundtrip(json!({"range": range_json, "placeholder": "main"}), |c| c.prepare_rename(position_params()));
+        assert_eq!(resp, Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range,
+            placeholder: "main".to_string(),
+        }));
+
+        let (resp, _req) = test_util::roundtrip(Value::Null, |c| c.prepare_rename(position_params()));
+        assert_eq!(resp, None);
+    }
+
+    fn call_hierarchy_item() -> Value {
+        let range = json!({"start": {"line": 0, "character": 3}, "end": {"line": 0, "character": 7}});
+        json!({
+            "name": "main",
+            "kind": 12,
+            "uri": "file:///tmp/main.rs",
+            "range": range,
+            "selectionRange": range,
+        })
+    }
+
+    #[test]
+    fn test_prepare_call_hierarchy() {
+        let params = serde_json::from_value(json!({
+            "textDocument": {"uri": "file:///tmp/main.rs"},
+            "position": {"line": 0, "character": 4},
+        })).unwrap();
+        let (resp, req) = test_util::roundtrip(json!([call_hierarchy_item()]), move |c| c.prepare_call_hierarchy(params));
+        assert_eq!(req.method, "textDocument/prepareCallHierarchy");
+        assert_eq!(resp.unwrap()[0].name, "main");
+    }
+
+    #[test]
+    fn test_incoming_calls() {
+        let params = serde_json::from_value(json!({"item": call_hierarchy_item()})).unwrap();
+        let (_resp, req) = test_util::roundtrip(json!([]), move |c| c.incoming_calls(params));
+        assert_eq!(req.method, "callHierarchy/incomingCalls");
+    }
+
+    #[test]
+    fn test_outgoing_calls() {
+        let params = serde_json::from_value(json!({"item": call_hierarchy_item()})).unwrap();
+        let (_resp, req) = test_util::roundtrip(json!([]), move |c| c.outgoing_calls(params));
+        assert_eq!(req.method, "callHierarchy/outgoingCalls");
+    }
+
+    #[test]
+    fn test_semantic_tokens_full() {
+        let params = serde_json::from_value(json!({"textDocument": {"uri": "file:///tmp/main.rs"}})).unwrap();
+        let (resp, req) = test_util::roundtrip(json!({"data": [0, 3, 4, 1, 0]}), move |c| c.semantic_tokens_full(params));
+        assert_eq!(req.method, "textDocument/semanticTokens/full");
+        assert!(resp.is_some());
+    }
+
+    #[test]
+    fn test_semantic_tokens_range() {
+        let params = serde_json::from_value(json!({
+            "textDocument": {"uri": "file:///tmp/main.rs"},
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 1, "character": 0}},
+        })).unwrap();
+        let (resp, req) = test_util::roundtrip(json!({"data": [0, 3, 4, 1, 0]}), move |c| c.semantic_tokens_range(params));
+        assert_eq!(req.method, "textDocument/semanticTokens/range");
+        assert!(resp.is_some());
+    }
+
+    #[test]
+    fn test_document_diagnostics() {
+        let params = || serde_json::from_value(json!({"textDocument": {"uri": "file:///tmp/main.rs"}})).unwrap();
+        let (resp, req) = test_util::roundtrip(json!({"kind": "full", "resultId": "1", "items": []}), move |c| c.document_diagnostics(params()));
+        assert_eq!(req.method, "textDocument/diagnostic");
+        assert_eq!(req.params, Some(json!({"textDocument": {"uri": "file:///tmp/main.rs"}})));
+        match resp {
+            ::types::DocumentDiagnosticReport::Full(report) => assert!(report.items.is_empty()),
+            r => panic!("unexpected report: {:?}", r),
+        }
+        let (resp, _req) = test_util::roundtrip(json!({"kind": "unchanged", "resultId": "1"}), move |c| c.document_diagnostics(params()));
+        match resp {
+            ::types::DocumentDiagnosticReport::Unchanged(report) => assert_eq!(report.result_id, "1"),
+            r => panic!("unexpected report: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_inlay_hint() {
+        let params = serde_json::from_value(json!({
+            "textDocument": {"uri": "file:///tmp/main.rs"},
+            "range": {"start": {"line": 0, "character": 0}, "end": {"line": 10, "character": 0}},
+        })).unwrap();
+        let (resp, req) = test_util::roundtrip(json!([inlay_hint()]), move |c| c.inlay_hint(params));
+        assert_eq!(req.method, "textDocument/inlayHint");
+        assert_eq!(resp.map(|hints| hints.len()), Some(1));
+    }
+
+    #[test]
+    fn test_inlay_hint_resolve() {
+        let hint = serde_json::from_value(inlay_hint()).unwrap();
+        let (_resp, req) = test_util::roundtrip(inlay_hint(), move |c| c.inlay_hint_resolve(hint));
+        assert_eq!(req.method, "inlayHint/resolve");
+        assert_eq!(req.params, Some(inlay_hint()));
+    }
+
+    #[test]
+    fn test_resolve_workspace_symbol() {
+        let symbol = json!({
+            "name": "main",
+            "kind": 12,
+            "location": {"uri": "file:///tmp/main.rs"},
+        });
+        let params = serde_json::from_value(symbol.clone()).unwrap();
+        let (_resp, req) = test_util::roundtrip(symbol.clone(), move |c| c.resolve_workspace_symbol(params));
+        assert_eq!(req.method, "workspaceSymbol/resolve");
+        assert_eq!(req.params, Some(symbol));
+    }
+
+    fn inlay_hint() -> Value {
+        json!({"position": {"line": 1, "character": 9}, "label": ": u32"})
+    }
+
+    #[test]
+    fn test_call_raw() {
+        let (resp, req) = test_util::roundtrip(json!({"crates": ["tokio_lsp"]}), |c| {
+            c.call_raw("rust-analyzer/viewCrateGraph", json!({"full": true}))
+        });
+        assert_eq!(req.method, "rust-analyzer/viewCrateGraph");
+        assert_eq!(req.params, Some(json!({"full": true})));
+        assert_eq!(resp, json!({"crates": ["tokio_lsp"]}));
+    }
+
+    #[test]
+    fn test_notify_raw() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        client.notify_raw("rustDocument/beginBuild", Value::Null).unwrap();
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => assert_eq!(not.method, "rustDocument/beginBuild"),
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    /// Answers every request from the server with a future which never
+    /// completes, noting when it is dropped.
+    struct Stalled(Rc<Cell<bool>>);
+
+    struct DropFlag(Rc<Cell<bool>>);
+
+    impl Drop for DropFlag {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    impl server::Server for Stalled {
+        type Success = Value;
+        type RpcCallResult = Box<Future<Item=Value, Error=RpcError>>;
+        type NotificationResult = Result<(), ()>;
+
+        fn rpc(&self, _ctl: &ServerCtl, _method: &str, _params: &Option<Value>) -> Option<Self::RpcCallResult> {
+            let flag = DropFlag(self.0.clone());
+            Some(Box::new(future::empty().map(move |result| {
+                let _ = &flag;
+                result
+            })))
+        }
+    }
+
+    #[test]
+    fn test_server_cancels_request() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let dropped = Rc::new(Cell::new(false));
+        let _client = Client::with_notification_handler(pipe, Stalled(dropped.clone()), &handle);
+
+        let request = Message::request("workspace/configuration".to_string(), Some(json!({"items": []})));
+        let id = match request {
+            Message::Request(ref req) => req.id.clone(),
+            _ => unreachable!(),
+        };
+        remote.to_client.unbounded_send(Ok(request)).unwrap();
+        test_util::settle(&mut core);
+        assert!(!dropped.get());
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "$/cancelRequest".to_string(),
+            Some(json!({"id": id.clone()})),
+        ))).unwrap();
+        let (msg, _from_client) = test_util::recv(&mut core, remote.from_client);
+        assert!(dropped.get());
+        match msg {
+            Message::Response(ref resp) => {
+                assert_eq!(resp.id, id);
+                match resp.result {
+                    Err(ref e) => assert_eq!(e.code, -32800),
+                    ref r => panic!("unexpected result: {:?}", r),
+                }
+            },
+            msg => panic!("unexpected message: {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_clone() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |req| match req.method.as_str() {
+            "textDocument/hover" => Some(json!({"contents": "docs"})),
+            _ => Some(json!([])),
+        });
+        let mut client = Client::new(pipe, &handle);
+        let mut other = client.clone();
+
+        let (hover_id, hover) = client.call_returning_id::<lsp_request!("textDocument/hover")>(position_params());
+        let (symbols_id, symbols) = other.call_returning_id::<lsp_request!("textDocument/documentSymbol")>(serde_json::from_value(json!({
+            "textDocument": {"uri": "file:///tmp/main.rs"},
+        })).unwrap());
+        assert_ne!(hover_id, symbols_id);
+
+        let (hover, symbols) = core.run(hover.join(symbols)).unwrap();
+        assert!(hover.is_some());
+        assert!(symbols.is_some());
+        assert_eq!(received.borrow().len(), 2);
+    }
+
+    #[test]
+    fn test_is_alive() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let client = Client::new(pipe, &handle);
+
+        test_util::settle(&mut core);
+        assert!(client.is_alive());
+        drop(remote);
+        test_util::settle(&mut core);
+        assert!(!client.is_alive());
+    }
+
+    #[test]
+    fn test_connection_closed() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let mut client = Client::new(pipe, &handle);
+
+        let call = client.shutdown(());
+        drop(remote);
+        match core.run(call) {
+            Err(LspError::ConnectionClosed) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_driver() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (client, driver) = Client::new_with_driver(pipe, &handle);
+
+        drop(remote);
+        drop(client);
+        core.run(driver).unwrap();
+    }
+
+    #[test]
+    fn test_execute_command() {
+        let params = ExecuteCommandParams {
+            command: "rls.applySuggestion".to_string(),
+            arguments: vec![json!("fix")],
+        };
+        let (_resp, req) = test_util::roundtrip(Value::Null, move |c| c.execute_command(params));
+        assert_eq!(req.method, "workspace/executeCommand");
+        assert_eq!(req.params, Some(json!({"command": "rls.applySuggestion", "arguments": ["fix"]})));
+    }
+
+    #[test]
+    fn test_will_save_wait_until() {
+        let params = WillSaveTextDocumentParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap()),
+            reason: TextDocumentSaveReason::Manual,
+        };
+        let (resp, req) = test_util::roundtrip(Value::Null, move |c| c.will_save_wait_until(params));
+        assert_eq!(req.method, "textDocument/willSaveWaitUntil");
+        assert_eq!(resp, None);
+    }
+
+    #[test]
+    fn test_shutdown_and_exit() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(Value::Null));
+        let client = Client::new(pipe, &handle);
+
+        core.run(client.shutdown_and_exit()).unwrap();
+        test_util::settle(&mut core);
+        let received = received.borrow();
+        match (&received[0], &received[1]) {
+            (&Message::Request(ref req), &Message::Notification(ref not)) => {
+                assert_eq!(req.method, "shutdown");
+                assert_eq!(not.method, "exit");
+            },
+            m => panic!("unexpected messages: {:?}", m),
+        }
+    }
+
+    #[test]
+    fn test_initialized() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+
+        client.initialized(InitializedParams {}).unwrap();
+        test_util::settle(&mut core);
+        match received.borrow()[0] {
+            Message::Notification(ref not) => assert_eq!(not.method, "initialized"),
+            ref m => panic!("unexpected message: {:?}", m),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_spawn() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        // `cat` echoes our own notifications back at us
+        let (collector, diagnostics) = DiagnosticsCollector::new();
+        let mut client = Client::spawn_with_notification_handler(Command::new("cat"), collector, &handle).unwrap();
+
+        client.notify::<lsp_notification!("textDocument/publishDiagnostics")>(PublishDiagnosticsParams {
+            uri: "file:///tmp/main.rs".parse().unwrap(),
+            diagnostics: vec![],
+        }).unwrap();
+        let (params, _diagnostics) = test_util::recv(&mut core, diagnostics);
+        assert_eq!(params.uri.as_str(), "file:///tmp/main.rs");
+    }
+
+    /// A stub server which answers `shutdown`, and exits with the code
+    /// given as its first argument on `exit`.
+    #[cfg(unix)]
+    fn stub_server(code: i32) -> Command {
+        let script = r#"
+            cr=$(printf '\r')
+            while :; do
+                len=
+                while IFS= read -r line; do
+                    line=${line%$cr}
+                    [ -z "$line" ] && break
+                    case $line in Content-Length:*) len=${line#Content-Length: };; esac
+                done
+                [ -n "$len" ] || exit 2
+                body=$(head -c "$len")
+                case $body in
+                    *'"shutdown"'*)
+                        id=$(printf '%s' "$body" | sed 's/.*"id":\([^,}]*\).*/\1/')
+                        resp="{\"jsonrpc\":\"2.0\",\"id\":$id,\"result\":null}"
+                        printf 'Content-Length: %d\r\n\r\n%s' ${#resp} "$resp";;
+                    *'"exit"'*) exit $1;;
+                esac
+            done
+        "#;
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg(script).arg("stub").arg(code.to_string());
+        cmd
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shutdown_exit_code() {
+        let mut core = Core::new().unwrap();
+        let client = Client::spawn(stub_server(0), &core.handle()).unwrap();
+        core.run(client.shutdown_and_exit()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shutdown_unexpected_exit_code() {
+        let mut core = Core::new().unwrap();
+        let client = Client::spawn(stub_server(1), &core.handle()).unwrap();
+        match core.run(client.shutdown_and_exit()) {
+            Err(LspError::UnexpectedExit(status)) => assert_eq!(status.code(), Some(1)),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_exit_with_request_in_flight() {
+        let mut core = Core::new().unwrap();
+        let mut cmd = Command::new("sh");
+        // Exits as soon as the request starts arriving
+        cmd.arg("-c").arg("head -c 1 >/dev/null; exit 3");
+        let mut client = Client::spawn(cmd, &core.handle()).unwrap();
+        match core.run(client.shutdown(())) {
+            Err(LspError::UnexpectedExit(status)) => assert_eq!(status.code(), Some(3)),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!client.is_alive());
+    }
+
+    #[test]
+    fn test_transport_error_in_flight() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let mut client = Client::new(pipe, &handle);
+
+        let call = client.shutdown(());
+        // Sending the request now fails
+        drop(remote);
+        match core.run(call) {
+            Err(LspError::ConnectionClosed) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(!client.is_alive());
+        assert!(client.outstanding().is_empty());
+    }
+
+    #[test]
+    fn test_queue_full() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| None);
+        let mut client = Client::new(pipe, &handle);
+        client.set_queue_capacity(8);
+
+        // Nothing drains while the reactor is not running
+        let results: Vec<_> = (0..100).map(|_| client.initialized(InitializedParams {})).collect();
+        assert!(results[..8].iter().all(Result::is_ok));
+        assert!(results[8..].iter().all(|r| match *r {
+            Err(LspError::QueueFull) => true,
+            _ => false,
+        }));
+
+        test_util::settle(&mut core);
+        assert_eq!(received.borrow().len(), 8);
+        client.initialized(InitializedParams {}).unwrap();
+    }
+
+    #[test]
+    fn test_sent_in_order() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let received = test_util::serve(remote, &handle, |_req| Some(Value::Null));
+        let mut client = Client::new(pipe, &handle);
+
+        let uri: Url = "file:///tmp/main.rs".parse().unwrap();
+        client.did_open_text_document(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), "rust".to_string(), 1, "fn main() {}".to_string()),
+        }).unwrap();
+        let hover = client.hover(position_params());
+        client.did_close_text_document(DidCloseTextDocumentParams {
+            text_document: TextDocumentIdentifier::new(uri),
+        }).unwrap();
+        // The call is polled before the notifications are sent in the background
+        core.run(hover).unwrap();
+        test_util::settle(&mut core);
+
+        let methods: Vec<_> = received.borrow().iter().map(|msg| match *msg {
+            Message::Request(ref req) => req.method.clone(),
+            Message::Notification(ref not) => not.method.clone(),
+            ref m => panic!("unexpected message: {:?}", m),
+        }).collect();
+        assert_eq!(methods, vec!["textDocument/didOpen", "textDocument/hover", "textDocument/didClose"]);
+    }
+
+    #[test]
+    fn test_null_result() {
+        let (resp, req) = test_util::roundtrip(Value::Null, |c| c.hover(position_params()));
+        assert_eq!(req.method, "textDocument/hover");
+        assert_eq!(resp, None);
+    }
+
+    #[test]
+    fn test_goto_type_definition() {
+        let (_resp, req) = test_util::roundtrip(json!([]), |c| c.goto_type_definition(position_params()));
+        assert_eq!(req.method, "textDocument/typeDefinition");
+    }
+
+    #[test]
+    fn test_goto_implementation() {
+        let (_resp, req) = test_util::roundtrip(json!([]), |c| c.goto_implementation(position_params()));
+        assert_eq!(req.method, "textDocument/implementation");
+    }
+
+    #[test]
+    fn test_goto_declaration() {
+        let (_resp, req) = test_util::roundtrip(json!([]), |c| c.goto_declaration(position_params()));
+        assert_eq!(req.method, "textDocument/declaration");
+    }
+
+    #[test]
+    fn test_folding_range() {
+        let params = FoldingRangeParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap()),
+        };
+        let (resp, req) = test_util::roundtrip(json!([{"startLine": 1, "endLine": 4}]), move |c| c.folding_range(params));
+        assert_eq!(req.method, "textDocument/foldingRange");
+        assert_eq!(resp.unwrap()[0].end_line, 4);
+    }
+
+    #[test]
+    fn test_selection_range() {
+        let params = SelectionRangeParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap()),
+            positions: vec![Position::new(0, 1), Position::new(2, 3)],
+        };
+        let range = json!({"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 5}});
+        let (resp, req) = test_util::roundtrip(json!([{"range": range}, {"range": range}]), move |c| c.selection_range(params));
+        assert_eq!(req.method, "textDocument/selectionRange");
+        assert_eq!(req.params.unwrap()["positions"], json!([{"line": 0, "character": 1}, {"line": 2, "character": 3}]));
+        assert_eq!(resp.unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_document_color() {
+        let params = DocumentColorParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/style.css".parse().unwrap()),
+        };
+        let (resp, req) = test_util::roundtrip(json!([]), move |c| c.document_color(params));
+        assert_eq!(req.method, "textDocument/documentColor");
+        assert_eq!(req.params, Some(json!({"textDocument": {"uri": "file:///tmp/style.css"}})));
+        assert!(resp.is_empty());
+    }
+
+    #[test]
+    fn test_color_presentation() {
+        let params = ColorPresentationParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/style.css".parse().unwrap()),
+            color: Color { red: 1.0, green: 0.0, blue: 0.0, alpha: 1.0 },
+            range: Range::new(Position::new(0, 7), Position::new(0, 14)),
+        };
+        let (_resp, req) = test_util::roundtrip(json!([{"label": "#ff0000"}]), move |c| c.color_presentation(params));
+        assert_eq!(req.method, "textDocument/colorPresentation");
+        assert_eq!(req.params.unwrap()["color"], json!({"red": 1.0, "green": 0.0, "blue": 0.0, "alpha": 1.0}));
+    }
 }
@@ -1,9 +1,11 @@
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
 use futures::sync::oneshot::{self, Receiver, Sender};
 use jsonrpc::{message, ServerCtl};
 use jsonrpc::server::{AbstractServer, ServerChain};
 use ls_types::*;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
+use std::cmp;
 use std::ops::Deref;
 
 use super::*;
@@ -19,18 +21,180 @@ use super::*;
 pub struct RlsClient {
     inner: Client,
     pub(crate) init_done: Option<Receiver<()>>,
+    reconnect: Option<Reconnect>,
+}
+
+/// How a client retries when the connection to the server fails, see
+/// `RlsClient::spawn_with_reconnect` and `Client::connect_and_initialize`.
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    /// The number of consecutive retries to attempt before giving up.
+    pub max_retries: u32,
+    /// How long to wait before retrying, e.g. to give a relaunched server
+    /// time to start. This is doubled with each consecutive attempt, up to
+    /// a minute.
+    pub backoff: Duration,
+}
+
+/// The longest a `ReconnectPolicy` waits between two attempts, in seconds.
+const MAX_BACKOFF_SECS: u64 = 60;
+
+impl ReconnectPolicy {
+    /// How long to wait before the retry following `attempt` earlier ones.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let max = Duration::from_secs(MAX_BACKOFF_SECS);
+        2u32.checked_pow(attempt)
+            .and_then(|factor| self.backoff.checked_mul(factor))
+            .map_or(max, |delay| cmp::min(delay, max))
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_retries: 3,
+            backoff: Duration::from_millis(100),
+        }
+    }
+}
+
+/// State for relaunching RLS.
+struct Reconnect {
+    command: Box<Fn() -> Command>,
+    policy: ReconnectPolicy,
+    /// The parameters to replay `initialize` with.
+    init_params: Option<InitializeParams>,
+    /// Set when a call fails because of the connection.
+    failed: Rc<Cell<bool>>,
+    /// Relaunches since the last successful call.
+    attempts: Rc<Cell<u32>>,
+    reconnects: u32,
 }
 
 impl RlsClient {
     /// Perform the initialize notification, and provide a future to block 
     /// the client for making more calls until the building/indexing has finished.
-    pub fn initialize_and_wait(mut self, params: InitializeParams) -> Box<Future<Item=(Self, Result<InitializeResult, InitializeError>), Error=IoError>> {
+    ///
+    /// The `initialized` notification is sent as soon as the server responds.
+    pub fn initialize_and_wait(mut self, params: InitializeParams) -> Box<Future<Item=(Self, Result<InitializeResult, InitializeError>), Error=LspError>> {
+        let init_done = self.init_done.take().expect("attempted to initialize multiple times").map_err(|_e| LspError::Transport(custom_err("notification handlers cancelled")));
         Box::new(self.initialize(params)
-        .join(self.init_done.take().expect("attempted to initialize multiple times").map_err(|_e| custom_err("notification handlers cancelled")))
-        .and_then(|(resp, _)| {
+        .and_then(move |resp| {
+            self.initialized(InitializedParams {})?;
             Ok((self, resp))
+        })
+        .join(init_done)
+        .map(|(res, _)| res))
+    }
+
+    /// Like `initialize_and_wait`, but stops waiting for the building/indexing
+    /// to finish after `timeout`.
+    ///
+    /// The client is returned either way, along with `Err(LspError::Timeout)`
+    /// if RLS was not done in time. The timeout only covers the wait, not the
+    /// response to `initialize`.
+    pub fn initialize_and_wait_timeout(mut self, params: InitializeParams, timeout: Duration)
+        -> Box<Future<Item=(Self, Result<InitializeResult, InitializeError>, Result<(), LspError>), Error=LspError>>
+    {
+        let init_done = self.init_done.take().expect("attempted to initialize multiple times");
+        let timer = match Timeout::new(timeout, &self.inner.handle) {
+            Ok(t) => t,
+            Err(e) => return Box::new(future::err(LspError::Transport(e))),
+        };
+        let waited = init_done.select2(timer).then(|res| {
+            match res {
+                Ok(Either::A(((), _timer))) => Ok(Ok(())),
+                Ok(Either::B(((), _init_done))) => Ok(Err(LspError::Timeout)),
+                Err(Either::A((_cancelled, _timer))) => Err(LspError::Transport(custom_err("notification handlers cancelled"))),
+                Err(Either::B((e, _init_done))) => Err(LspError::Transport(e)),
+            }
+        });
+        Box::new(self.initialize(params)
+        .and_then(move |resp| {
+            self.initialized(InitializedParams {})?;
+            Ok((self, resp))
+        })
+        .join(waited)
+        .map(|((client, resp), waited)| (client, resp, waited)))
+    }
+
+    /// The capabilities of the server, once it has been initialized.
+    pub fn capabilities(&self) -> Option<Ref<ServerCapabilities>> {
+        self.inner.capabilities()
+    }
+
+    /// Whether the connection to RLS is still open, see `Client::is_alive`.
+    pub fn is_alive(&self) -> bool {
+        self.inner.is_alive()
+    }
+
+    /// The number of times RLS has been relaunched, see `spawn_with_reconnect`.
+    pub fn reconnects(&self) -> u32 {
+        self.reconnect.as_ref().map_or(0, |r| r.reconnects)
+    }
+
+    /// If the connection to RLS failed, launch it again and replay the
+    /// `initialize` call.
+    ///
+    /// The new client keeps the settings of the old one, see
+    /// `Client::respawn_like`.
+    ///
+    /// Returns a future which resolves once the new server is ready for more
+    /// messages (after `initialize`, if it had been called), or `None` if
+    /// there was no need to reconnect.
+    fn reconnect_if_failed(&mut self) -> Result<Option<Box<Future<Item=(), Error=LspError>>>, LspError> {
+        let reconnect = match self.reconnect {
+            Some(ref mut r) if r.failed.get() => r,
+            _ => return Ok(None),
+        };
+        let attempts = reconnect.attempts.get();
+        if attempts >= reconnect.policy.max_retries {
+            return Err(LspError::Transport(custom_err("RLS failed too many times, giving up")));
+        }
+
+        let handle = self.inner.handle.clone();
+        let (server, init_done) = WaitForInit::new();
+        self.inner = Client::respawn_like(&self.inner, (reconnect.command)(), server)?;
+        self.init_done = Some(init_done);
+        reconnect.attempts.set(attempts + 1);
+        reconnect.failed.set(false);
+        reconnect.reconnects += 1;
+
+        let timer = Timeout::new(reconnect.policy.delay(attempts), &handle)?
+            .map_err(LspError::Transport);
+        match reconnect.init_params {
+            Some(ref params) => {
+                let init = self.inner.initialize(params.clone());
+                let initialized = self.inner.notify_sent::<lsp_notification!("initialized")>(InitializedParams {});
+                Ok(Some(Box::new(timer.and_then(move |()| init).and_then(move |_resp| initialized))))
+            },
+            None => Ok(Some(Box::new(timer))),
+        }
+    }
+
+    /// Keep track of whether `call` failed because of the connection, when
+    /// reconnecting is enabled.
+    fn watch<T: 'static>(&self, call: Box<Future<Item=T, Error=LspError>>) -> Box<Future<Item=T, Error=LspError>> {
+        let (failed, attempts) = match self.reconnect {
+            Some(ref r) => (r.failed.clone(), r.attempts.clone()),
+            None => return call,
+        };
+        Box::new(call.then(move |res| {
+            match res {
+                Err(LspError::Transport(_)) | Err(LspError::MissingResponse) |
+                Err(LspError::ConnectionClosed) | Err(LspError::UnexpectedExit(_)) => failed.set(true),
+                _ => attempts.set(0),
+            }
+            res
         }))
     }
+
+    /// Send `shutdown` followed by `exit`, see `Client::shutdown_and_exit`.
+    ///
+    /// If RLS was launched by the client, the process is dropped afterwards.
+    pub fn shutdown_and_exit(self) -> Box<Future<Item=(), Error=LspError>> {
+        self.inner.shutdown_and_exit()
+    }
 }
 
 
@@ -47,9 +211,59 @@ impl RlsClient {
         Self {
             inner: Client::with_notification_handler(connection, server, handle),
             init_done: Some(init_done),
+            reconnect: None,
         }
     }
 
+    /// Launch RLS using `cmd` and communicate with it over stdin/stdout.
+    ///
+    /// The process is killed when the client is dropped.
+    pub fn spawn(cmd: Command, handle: &Handle) -> IoResult<Self> {
+        let (server, init_done) = WaitForInit::new();
+        Ok(Self {
+            inner: Client::spawn_with_notification_handler(cmd, server, handle)?,
+            init_done: Some(init_done),
+            reconnect: None,
+        })
+    }
+
+    /// Launch RLS using the command built by `command`, and launch it again
+    /// (replaying the `initialize` call) whenever the connection fails.
+    ///
+    /// A call which fails because of the connection still returns the error;
+    /// the server is relaunched on the next call.
+    pub fn spawn_with_reconnect<F>(command: F, policy: ReconnectPolicy, handle: &Handle) -> IoResult<Self>
+        where F: Fn() -> Command + 'static
+    {
+        let mut client = Self::spawn(command(), handle)?;
+        client.reconnect = Some(Reconnect {
+            command: Box::new(command),
+            policy,
+            init_params: None,
+            failed: Rc::new(Cell::new(false)),
+            attempts: Rc::new(Cell::new(0)),
+            reconnects: 0,
+        });
+        Ok(client)
+    }
+
+    /// Create a new `Client`, along with a stream of the build/indexing
+    /// progress reported by RLS.
+    pub fn with_progress<C>(connection: C, handle: &Handle) -> (Self, UnboundedReceiver<RemoteState>)
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+    {
+        let (server, init_done, progress) = WaitForInit::with_progress();
+        (Self {
+            inner: Client::with_notification_handler(connection, server, handle),
+            init_done: Some(init_done),
+            reconnect: None,
+        },
+        progress)
+    }
+
     /// Create a new `Client` with a provided handler to handle incoming notifications.
     pub fn with_notification_handler<C, NH>(connection: C, notification_handler: NH, handle: &Handle) -> Self
         where
@@ -67,27 +281,49 @@ impl RlsClient {
         Self {
             inner: Client::with_notification_handler(connection, chain, handle),
             init_done: Some(init_done),
+            reconnect: None,
         }
     }
 }
 
-/// A `Server' implementation which handles the custom RLS notification, and
-/// lets a `Receiver` know when the remote server has finished building the code.
+/// A `Server' implementation which handles the `window/progress` notification,
+/// and lets a `Receiver` know when the remote server has finished building the code.
+///
+/// Initialization is finished once the final phase (by default RLS's
+/// "Indexing") reports that it is done.
 pub struct WaitForInit {
     state: RefCell<RemoteState>,
     sender: RefCell<Option<Sender<()>>>,
     ctl: RefCell<Option<ServerCtl>>,
+    progress: Option<UnboundedSender<RemoteState>>,
+    unhandled: Option<UnboundedSender<(String, Option<serde_json::Value>)>>,
+    final_phase: String,
 }
 
+/// The phase of RLS's startup, as reported through `window/progress`.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum RemoteState {
     Closed,
     Init,
     Building,
     Indexing,
+    /// Working on a phase other than the RLS ones.
+    InProgress,
     Done,
     Unknown,
 }
 
+impl RemoteState {
+    /// The state corresponding to working on the phase with the given title.
+    fn from_phase(title: &str) -> Self {
+        match title {
+            "Building" => RemoteState::Building,
+            "Indexing" => RemoteState::Indexing,
+            _ => RemoteState::InProgress,
+        }
+    }
+}
+
 impl WaitForInit {
     pub fn new() -> (Self, Receiver<()>) {
         let (sender, receiver) = oneshot::channel();
@@ -95,26 +331,56 @@ impl WaitForInit {
             state: RefCell::new(RemoteState::Closed),
             sender: RefCell::new(Some(sender)),
             ctl: RefCell::new(None),
+            progress: None,
+            unhandled: None,
+            final_phase: "Indexing".to_string(),
         },
         receiver)
     }
 
+    /// Consider initialization finished when the phase with the given title is done.
+    pub fn finish_on<S: Into<String>>(mut self, title: S) -> Self {
+        self.final_phase = title.into();
+        self
+    }
+
+    /// Forward every notification other than `window/progress`, as its method
+    /// and raw params, to `sink`.
+    ///
+    /// The notifications are still left to the next handler in the chain.
+    ///
+    /// Useful to observe notifications this crate does not model.
+    pub fn forward_unhandled(mut self, sink: UnboundedSender<(String, Option<serde_json::Value>)>) -> Self {
+        self.unhandled = Some(sink);
+        self
+    }
+
+    /// Like `new`, but additionally forwards every change of the remote state
+    /// over the returned channel.
+    pub fn with_progress() -> (Self, Receiver<()>, UnboundedReceiver<RemoteState>) {
+        let (mut server, receiver) = Self::new();
+        let (sender, progress) = mpsc::unbounded();
+        server.progress = Some(sender);
+        (server, receiver, progress)
+    }
+
     fn update_state(&self, state: RemoteState) {
+        debug!("RLS state: {:?}", state);
+        if let Some(ref progress) = self.progress {
+            // Nobody listening any more is fine
+            let _ = progress.unbounded_send(state);
+        }
         self.state.replace(state);
     }
 }
 
+/// The parameters of a `window/progress` notification.
 #[derive(Debug, Deserialize, Serialize)]
-enum Phase {
-    Building,
-    Indexing,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct WindowProgress {
-    id: String,
+pub struct WindowProgress {
+    pub id: String,
+    pub title: String,
     pub message: Option<String>,
-    pub title: Phase,
+    pub percentage: Option<f64>,
     pub done: Option<bool>,
 }
 
@@ -130,29 +396,37 @@ impl server::Server for WaitForInit {
     }
 
     fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<serde_json::Value>) -> Option<Self::NotificationResult> {
+        if method != "window/progress" {
+            if let Some(ref sink) = self.unhandled {
+                // Nobody listening any more is fine
+                let _ = sink.unbounded_send((method.to_string(), params.clone()));
+            }
+            return None;
+        }
         if let RemoteState::Done = self.state.borrow().deref() {
             return None;
         }
-        if method == "window/progress" {
-            if let Some(Ok(params)) = params.clone().map(|p| serde_json::from_value::<WindowProgress>(p)) {
-                let state = match (&params.done, &params.title) {
-                    (Some(true), Phase::Indexing) => {
-                        self.sender.borrow_mut().take().expect("Should not be able to finish twice").send(()).unwrap();
-                        RemoteState::Done
-                    },
-                    (Some(true), Phase::Building) => RemoteState::Indexing,
-                    (_, Phase::Indexing) => RemoteState::Indexing,
-                    (_, Phase::Building) => RemoteState::Building,
-                };
-                self.update_state(state);
-                self.ctl.replace(None);
-                Some(Ok(()))
-            } else {
-                self.update_state(RemoteState::Unknown);
-                Some(Err(()))
-            }
+        if let Some(Ok(params)) = params.clone().map(|p| serde_json::from_value::<WindowProgress>(p)) {
+            let state = match (params.done, params.title == self.final_phase) {
+                (Some(true), true) => {
+                    // Only the first completion is reported, and whoever
+                    // was waiting for it may have given up already
+                    if let Some(sender) = self.sender.borrow_mut().take() {
+                        let _ = sender.send(());
+                    }
+                    RemoteState::Done
+                },
+                // Done with an earlier phase, so waiting on the final one
+                (Some(true), false) => RemoteState::from_phase(&self.final_phase),
+                (_, _) => RemoteState::from_phase(&params.title),
+            };
+            self.update_state(state);
+            self.ctl.replace(None);
+            Some(Ok(()))
         } else {
-            None
+            warn!("malformed window/progress notification: {:?}", params);
+            self.update_state(RemoteState::Unknown);
+            Some(Err(()))
         }
     }
 }
@@ -160,24 +434,46 @@ impl server::Server for WaitForInit {
 
 macro_rules! lscall {
     (@req $fn_name:ident, $name:tt) => {
-        fn $fn_name(&mut self, params: <lsp_request!($name) as Request>::Params) -> Box<Future<Item=<lsp_request!($name) as Request>::Result, Error=IoError>> {
-            self.inner.$fn_name(params)
+        fn $fn_name(&mut self, params: <request!($name) as Request>::Params) -> Box<Future<Item=<request!($name) as Request>::Result, Error=LspError>> {
+            let ready = match self.reconnect_if_failed() {
+                Ok(ready) => ready,
+                Err(e) => return Box::new(future::err(e)),
+            };
+            let call = self.inner.$fn_name(params);
+            match ready {
+                None => self.watch(call),
+                Some(ready) => self.watch(Box::new(ready.and_then(move |()| call))),
+            }
         }
     };
     (@notify $fn_name:ident, $name:tt) => {
-        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), IoError> {
-            self.inner.$fn_name(params)
+        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), LspError> {
+            match self.reconnect_if_failed()? {
+                None => self.inner.$fn_name(params),
+                Some(ready) => {
+                    // Hold the notification back until the new server is initialized
+                    let notification = self.inner.notify_sent::<lsp_notification!($name)>(params);
+                    self.inner.handle.spawn(ready.and_then(move |()| notification)
+                        .map_err(|e| error!("failed to send {}: {}", $name, e)));
+                    Ok(())
+                },
+            }
         }
     };
 }
 
 impl LspClient for RlsClient {
-    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=IoError>> {
+    type Error = LspError;
+
+    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=LspError>> {
+        if let Some(ref mut reconnect) = self.reconnect {
+            reconnect.init_params = Some(params.clone());
+        }
         self.inner.initialize(params)
     }
 
-    // lscall!(@notify $/cancelRequest, "$/cancelRequest");
-    // lscall!(@notify initialized, "initialized");
+    lscall!(@notify cancel_request, "$/cancelRequest");
+    lscall!(@notify initialized, "initialized");
     lscall!(@notify exit, "exit");
     // lscall!(@notify window/showMessage, "window/showMessage");
     // lscall!(@notify window/logMessage, "window/logMessage");
@@ -190,31 +486,275 @@ impl LspClient for RlsClient {
     // lscall!(@notify textDocument/publishDiagnostics, "textDocument/publishDiagnostics");
     lscall!(@notify did_change_configuration, "workspace/didChangeConfiguration");
     lscall!(@notify did_change_watched_files, "workspace/didChangeWatchedFiles");
+    lscall!(@notify did_change_workspace_folders, "workspace/didChangeWorkspaceFolders");
 
     // lscall!(@req initialize, "initialize");
     lscall!(@req shutdown, "shutdown");
-    // lscall!(@req window/showMessageRequest, "window/showMessageRequest");
-    // lscall!(@req client/registerCapability, "client/registerCapability");
-    // lscall!(@req client/unregisterCapability, "client/unregisterCapability");
+    // window/showMessageRequest is sent by the server, see `handlers::ShowMessageRequestHandler`
+    // client/registerCapability and client/unregisterCapability are sent by the server, see `handlers::RegistrationHandler`
     lscall!(@req workspace_symbols, "workspace/symbol");
-    // lscall!(@req workspace/executeCommand, "workspace/executeCommand");
-    // lscall!(@req textDocument/willSaveWaitUntil, "textDocument/willSaveWaitUntil");
+    lscall!(@req resolve_workspace_symbol, "workspaceSymbol/resolve");
+    lscall!(@req execute_command, "workspace/executeCommand");
+    lscall!(@req will_save_wait_until, "textDocument/willSaveWaitUntil");
     lscall!(@req completion, "textDocument/completion");
     lscall!(@req resolve_completion_item, "completionItem/resolve");
     lscall!(@req hover, "textDocument/hover");
     lscall!(@req signature_help, "textDocument/signatureHelp");
     lscall!(@req goto_definition, "textDocument/definition");
+    lscall!(@req goto_type_definition, "textDocument/typeDefinition");
+    lscall!(@req goto_implementation, "textDocument/implementation");
+    lscall!(@req goto_declaration, "textDocument/declaration");
     lscall!(@req references, "textDocument/references");
     lscall!(@req document_highlight, "textDocument/documentHighlight");
     lscall!(@req document_symbols, "textDocument/documentSymbol");
     lscall!(@req code_action, "textDocument/codeAction");
+    lscall!(@req resolve_code_action, "codeAction/resolve");
     lscall!(@req code_lens, "textDocument/codeLens");
     lscall!(@req code_lens_resolve, "codeLens/resolve");
     lscall!(@req document_link, "textDocument/documentLink");
     lscall!(@req document_link_resolve, "documentLink/resolve");
+    lscall!(@req folding_range, "textDocument/foldingRange");
+    lscall!(@req selection_range, "textDocument/selectionRange");
+    lscall!(@req document_color, "textDocument/documentColor");
+    lscall!(@req color_presentation, "textDocument/colorPresentation");
+    lscall!(@req prepare_call_hierarchy, "textDocument/prepareCallHierarchy");
+    lscall!(@req incoming_calls, "callHierarchy/incomingCalls");
+    lscall!(@req outgoing_calls, "callHierarchy/outgoingCalls");
+    lscall!(@req semantic_tokens_full, "textDocument/semanticTokens/full");
+    lscall!(@req semantic_tokens_range, "textDocument/semanticTokens/range");
+    lscall!(@req document_diagnostics, "textDocument/diagnostic");
+    lscall!(@req inlay_hint, "textDocument/inlayHint");
+    lscall!(@req inlay_hint_resolve, "inlayHint/resolve");
     // lscall!(@req textDocument/applyEdit, "textDocument/applyEdit");
     lscall!(@req range_formatting, "textDocument/rangeFormatting");
     lscall!(@req on_type_formatting, "textDocument/onTypeFormatting");
     lscall!(@req formatting, "textDocument/formatting");
     lscall!(@req rename, "textDocument/rename");
+    lscall!(@req prepare_rename, "textDocument/prepareRename");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn test_remote_state() {
+        let state = RemoteState::from_phase("Building");
+        let copy = state;
+        assert_eq!(state, copy);
+        assert_eq!(RemoteState::from_phase("Indexing"), RemoteState::Indexing);
+        assert_eq!(RemoteState::from_phase("Fetching"), RemoteState::InProgress);
+        assert_ne!(state, RemoteState::Done);
+        assert_eq!(format!("{:?}", state), "Building");
+    }
+
+    #[test]
+    fn test_progress_stream() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (_client, mut progress) = RlsClient::with_progress(pipe, &handle);
+
+        for (title, done) in vec![("Building", false), ("Building", true), ("Indexing", false), ("Indexing", true)] {
+            remote.to_client.unbounded_send(Ok(Message::notification(
+                "window/progress".to_string(),
+                Some(json!({"id": "progress", "title": title, "done": done})),
+            ))).unwrap();
+        }
+
+        let expected = vec![
+            RemoteState::Init,
+            RemoteState::Building,
+            RemoteState::Indexing,
+            RemoteState::Indexing,
+            RemoteState::Done,
+        ];
+        for state in expected {
+            let (received, rest) = test_util::recv(&mut core, progress);
+            assert_eq!(received, state);
+            progress = rest;
+        }
+    }
+
+    #[test]
+    fn test_forward_unhandled() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (sink, unhandled) = mpsc::unbounded();
+        let (server, _init_done) = WaitForInit::new();
+        let _client = Client::with_notification_handler(pipe, server.forward_unhandled(sink), &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "rustDocument/beginBuild".to_string(),
+            Some(json!({"crate": "tokio_lsp"})),
+        ))).unwrap();
+
+        let ((method, params), _unhandled) = test_util::recv(&mut core, unhandled);
+        assert_eq!(method, "rustDocument/beginBuild");
+        assert_eq!(params, Some(json!({"crate": "tokio_lsp"})));
+    }
+
+    #[test]
+    fn test_forward_unhandled_chained() {
+        struct Next(UnboundedSender<String>);
+
+        impl server::Server for Next {
+            type Success = ();
+            type RpcCallResult = Result<(), message::RpcError>;
+            type NotificationResult = Result<(), ()>;
+
+            fn notification(&self, _ctl: &ServerCtl, method: &str, _params: &Option<serde_json::Value>) -> Option<Self::NotificationResult> {
+                let _ = self.0.unbounded_send(method.to_string());
+                Some(Ok(()))
+            }
+        }
+
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (sink, unhandled) = mpsc::unbounded();
+        let (next, handled) = mpsc::unbounded();
+        let (server, _init_done) = WaitForInit::new();
+        let chain = ServerChain::new(vec![
+            Box::new(AbstractServer::new(server.forward_unhandled(sink))),
+            Box::new(AbstractServer::new(Next(next))),
+        ]);
+        let _client = Client::with_notification_handler(pipe, chain, &handle);
+
+        remote.to_client.unbounded_send(Ok(Message::notification("rustDocument/beginBuild".to_string(), None))).unwrap();
+
+        let ((method, _params), _unhandled) = test_util::recv(&mut core, unhandled);
+        assert_eq!(method, "rustDocument/beginBuild");
+        let (method, _handled) = test_util::recv(&mut core, handled);
+        assert_eq!(method, "rustDocument/beginBuild");
+    }
+
+    #[test]
+    fn test_initialize_and_wait_timeout() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        // RLS never reports that it has finished indexing
+        let _received = test_util::serve(remote, &handle, |_req| Some(json!({"capabilities": {}})));
+        let client = RlsClient::new(pipe, &handle);
+
+        let init = client.initialize_and_wait_timeout(test_util::init_params(), Duration::from_millis(50));
+        let (mut client, resp, waited) = core.run(init).unwrap();
+        assert!(resp.is_ok());
+        match waited {
+            Err(LspError::Timeout) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(core.run(client.shutdown(())).is_ok());
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        let policy = ReconnectPolicy { max_retries: 100, backoff: Duration::from_millis(100) };
+        assert_eq!(policy.delay(0), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(800));
+        assert_eq!(policy.delay(10), Duration::from_secs(MAX_BACKOFF_SECS));
+        assert_eq!(policy.delay(40), Duration::from_secs(MAX_BACKOFF_SECS));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reconnect() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        // A "server" which dies straight away
+        let mut client = RlsClient::spawn_with_reconnect(|| Command::new("true"), ReconnectPolicy::default(), &handle).unwrap();
+
+        assert!(core.run(client.shutdown(())).is_err());
+        assert_eq!(client.reconnects(), 0);
+        assert!(core.run(client.shutdown(())).is_err());
+        assert_eq!(client.reconnects(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_reconnect_keeps_settings() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let mut client = RlsClient::spawn_with_reconnect(|| Command::new("true"), ReconnectPolicy::default(), &handle).unwrap();
+        client.inner.check_capabilities(true);
+        client.inner.set_queue_capacity(3);
+        client.inner.set_position_encodings(vec![PositionEncoding::Utf8]);
+        client.inner.set_client_info(ClientInfo { name: "test".into(), version: None });
+        client.inner.set_observer(Box::new(|_| ()));
+        client.inner.buffer_notifications();
+
+        // Not initialized, but the caller still learns of the new server
+        client.reconnect.as_ref().unwrap().failed.set(true);
+        let ready = client.reconnect_if_failed().unwrap();
+        assert!(ready.is_some());
+        assert_eq!(client.reconnects(), 1);
+        assert!(client.inner.check_capabilities);
+        assert_eq!(client.inner.queue_capacity, 3);
+        assert_eq!(client.inner.position_encodings, vec![PositionEncoding::Utf8]);
+        assert_eq!(client.inner.client_info, Some(ClientInfo { name: "test".into(), version: None }));
+        assert!(client.inner.observer.lock().unwrap().is_some());
+        assert!(client.inner.inbox.lock().unwrap().is_some());
+        core.run(ready.unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_custom_final_phase() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (server, init_done) = WaitForInit::new();
+        let _client = Client::with_notification_handler(pipe, server.finish_on("Loading"), &handle);
+
+        for (title, done) in vec![("Fetching", false), ("Fetching", true), ("Loading", false), ("Loading", true)] {
+            remote.to_client.unbounded_send(Ok(Message::notification(
+                "window/progress".to_string(),
+                Some(json!({"id": "progress", "title": title, "done": done})),
+            ))).unwrap();
+        }
+        core.run(init_done).unwrap();
+    }
+
+    #[test]
+    fn test_finish_twice() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (server, init_done) = WaitForInit::new();
+        let _client = Client::with_notification_handler(pipe, server, &handle);
+
+        for _ in 0..2 {
+            remote.to_client.unbounded_send(Ok(Message::notification(
+                "window/progress".to_string(),
+                Some(json!({"id": "progress", "title": "Indexing", "done": true})),
+            ))).unwrap();
+        }
+        core.run(init_done).unwrap();
+        test_util::settle(&mut core);
+    }
+
+    #[test]
+    fn test_receiver_dropped() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (server, init_done, mut progress) = WaitForInit::with_progress();
+        let _client = Client::with_notification_handler(pipe, server, &handle);
+        drop(init_done);
+
+        remote.to_client.unbounded_send(Ok(Message::notification(
+            "window/progress".to_string(),
+            Some(json!({"id": "progress", "title": "Indexing", "done": true})),
+        ))).unwrap();
+        loop {
+            let (state, rest) = test_util::recv(&mut core, progress);
+            if state == RemoteState::Done {
+                break;
+            }
+            progress = rest;
+        }
+    }
 }
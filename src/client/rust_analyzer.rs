@@ -0,0 +1,309 @@
+//! A client for rust-analyzer.
+//!
+//! Unlike RLS, rust-analyzer reports its indexing progress through the
+//! standard `$/progress` notification.
+
+use futures::sync::oneshot::{self, Receiver, Sender};
+use jsonrpc::{message, ServerCtl};
+use jsonrpc::server::{AbstractServer, ServerChain};
+use ls_types::*;
+
+use std::cell::RefCell;
+
+use super::*;
+use super::handlers::{ProgressParams, WorkDoneProgress};
+
+
+/// A wrapper for a rust-analyzer client.
+///
+/// Behaves like a generic `Client`, but can wait for rust-analyzer to finish
+/// indexing the workspace before sending further messages.
+pub struct RustAnalyzerClient {
+    inner: Client,
+    pub(crate) init_done: Option<Receiver<()>>,
+}
+
+impl RustAnalyzerClient {
+    /// Create a new `RustAnalyzerClient` with the given connection and run
+    /// futures on the provided handle.
+    pub fn new<C>(connection: C, handle: &Handle) -> Self
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+    {
+        let (server, init_done) = WaitForIndexing::new();
+        Self {
+            inner: Client::with_notification_handler(connection, server, handle),
+            init_done: Some(init_done),
+        }
+    }
+
+    /// Launch rust-analyzer using `cmd` and communicate with it over
+    /// stdin/stdout.
+    ///
+    /// The process is killed when the client is dropped.
+    pub fn spawn(cmd: Command, handle: &Handle) -> IoResult<Self> {
+        let (server, init_done) = WaitForIndexing::new();
+        Ok(Self {
+            inner: Client::spawn_with_notification_handler(cmd, server, handle)?,
+            init_done: Some(init_done),
+        })
+    }
+
+    /// Create a new `RustAnalyzerClient` with a provided handler to handle
+    /// incoming notifications.
+    ///
+    /// The handler also sees the `$/progress` notifications, e.g. for a
+    /// `ProgressCollector`.
+    pub fn with_notification_handler<C, NH>(connection: C, notification_handler: NH, handle: &Handle) -> Self
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+            NH: server::Server + 'static
+    {
+        let (server, init_done) = WaitForIndexing::new();
+        let chain = ServerChain::new(
+            vec![
+                Box::new(AbstractServer::new(server)),
+                Box::new(AbstractServer::new(notification_handler)),
+            ]);
+        Self {
+            inner: Client::with_notification_handler(connection, chain, handle),
+            init_done: Some(init_done),
+        }
+    }
+
+    /// Perform the initialize call, and provide a future to block the client
+    /// for making more calls until rust-analyzer has finished indexing.
+    ///
+    /// The `initialized` notification is sent as soon as the server responds.
+    pub fn initialize_and_wait(mut self, params: InitializeParams) -> Box<Future<Item=(Self, Result<InitializeResult, InitializeError>), Error=LspError>> {
+        let init_done = self.init_done.take().expect("attempted to initialize multiple times").map_err(|_e| LspError::Transport(custom_err("notification handlers cancelled")));
+        Box::new(self.initialize(params)
+        .and_then(move |resp| {
+            self.initialized(InitializedParams {})?;
+            Ok((self, resp))
+        })
+        .join(init_done)
+        .map(|(res, _)| res))
+    }
+
+    /// The capabilities of the server, once it has been initialized.
+    pub fn capabilities(&self) -> Option<Ref<ServerCapabilities>> {
+        self.inner.capabilities()
+    }
+
+    /// Whether the connection to rust-analyzer is still open, see
+    /// `Client::is_alive`.
+    pub fn is_alive(&self) -> bool {
+        self.inner.is_alive()
+    }
+
+    /// Send `shutdown` followed by `exit`, see `Client::shutdown_and_exit`.
+    pub fn shutdown_and_exit(self) -> Box<Future<Item=(), Error=LspError>> {
+        self.inner.shutdown_and_exit()
+    }
+}
+
+/// A `Server` implementation which lets a `Receiver` know when
+/// rust-analyzer has finished indexing, as reported through `$/progress`.
+///
+/// It also accepts the server's `window/workDoneProgress/create` requests.
+/// Notifications are not consumed, so other handlers in a `ServerChain`
+/// still see them.
+pub struct WaitForIndexing {
+    sender: RefCell<Option<Sender<()>>>,
+    token: String,
+}
+
+impl WaitForIndexing {
+    pub fn new() -> (Self, Receiver<()>) {
+        let (sender, receiver) = oneshot::channel();
+        (WaitForIndexing {
+            sender: RefCell::new(Some(sender)),
+            token: "rustAnalyzer/Indexing".to_string(),
+        },
+        receiver)
+    }
+
+    /// Consider initialization finished when the progress with the given
+    /// token ends.
+    pub fn finish_on<S: Into<String>>(mut self, token: S) -> Self {
+        self.token = token.into();
+        self
+    }
+}
+
+impl server::Server for WaitForIndexing {
+    type Success = Value;
+    type RpcCallResult = Result<Value, message::RpcError>;
+    type NotificationResult = Result<(), ()>;
+
+    fn rpc(&self, _ctl: &ServerCtl, method: &str, _params: &Option<Value>) -> Option<Self::RpcCallResult> {
+        if method == "window/workDoneProgress/create" {
+            Some(Ok(Value::Null))
+        } else {
+            None
+        }
+    }
+
+    fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+        if method != "$/progress" || self.sender.borrow().is_none() {
+            return None;
+        }
+        match params.clone().map(|p| serde_json::from_value::<ProgressParams>(p)) {
+            Some(Ok(ProgressParams { token: NumberOrString::String(ref token), value: WorkDoneProgress::End { .. } })) if *token == self.token => {
+                debug!("rust-analyzer finished {}", token);
+                if let Some(sender) = self.sender.borrow_mut().take() {
+                    // Whoever was waiting may have given up already
+                    let _ = sender.send(());
+                }
+            },
+            Some(Ok(_)) => (),
+            _ => warn!("malformed $/progress notification: {:?}", params),
+        }
+        None
+    }
+}
+
+
+macro_rules! lscall {
+    (@req $fn_name:ident, $name:tt) => {
+        fn $fn_name(&mut self, params: <request!($name) as Request>::Params) -> Box<Future<Item=<request!($name) as Request>::Result, Error=LspError>> {
+            self.inner.$fn_name(params)
+        }
+    };
+    (@notify $fn_name:ident, $name:tt) => {
+        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), LspError> {
+            self.inner.$fn_name(params)
+        }
+    };
+}
+
+impl LspClient for RustAnalyzerClient {
+    type Error = LspError;
+
+    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=LspError>> {
+        self.inner.initialize(params)
+    }
+
+    lscall!(@notify cancel_request, "$/cancelRequest");
+    lscall!(@notify initialized, "initialized");
+    lscall!(@notify exit, "exit");
+    // lscall!(@notify window/showMessage, "window/showMessage");
+    // lscall!(@notify window/logMessage, "window/logMessage");
+    // lscall!(@notify telemetry/event, "telemetry/event");
+    lscall!(@notify did_open_text_document, "textDocument/didOpen");
+    lscall!(@notify did_change_text_document, "textDocument/didChange");
+    // lscall!(@notify textDocument/willSave, "textDocument/willSave");
+    lscall!(@notify did_save_text_document, "textDocument/didSave");
+    lscall!(@notify did_close_text_document, "textDocument/didClose");
+    // lscall!(@notify textDocument/publishDiagnostics, "textDocument/publishDiagnostics");
+    lscall!(@notify did_change_configuration, "workspace/didChangeConfiguration");
+    lscall!(@notify did_change_watched_files, "workspace/didChangeWatchedFiles");
+    lscall!(@notify did_change_workspace_folders, "workspace/didChangeWorkspaceFolders");
+    // lscall!(@req initialize, "initialize");
+    lscall!(@req shutdown, "shutdown");
+    // client/registerCapability and client/unregisterCapability are sent by the server, see `handlers::RegistrationHandler`
+    lscall!(@req workspace_symbols, "workspace/symbol");
+    lscall!(@req resolve_workspace_symbol, "workspaceSymbol/resolve");
+    lscall!(@req execute_command, "workspace/executeCommand");
+    lscall!(@req will_save_wait_until, "textDocument/willSaveWaitUntil");
+    lscall!(@req completion, "textDocument/completion");
+    lscall!(@req resolve_completion_item, "completionItem/resolve");
+    lscall!(@req hover, "textDocument/hover");
+    lscall!(@req signature_help, "textDocument/signatureHelp");
+    lscall!(@req goto_definition, "textDocument/definition");
+    lscall!(@req goto_type_definition, "textDocument/typeDefinition");
+    lscall!(@req goto_implementation, "textDocument/implementation");
+    lscall!(@req goto_declaration, "textDocument/declaration");
+    lscall!(@req references, "textDocument/references");
+    lscall!(@req document_highlight, "textDocument/documentHighlight");
+    lscall!(@req document_symbols, "textDocument/documentSymbol");
+    lscall!(@req code_action, "textDocument/codeAction");
+    lscall!(@req resolve_code_action, "codeAction/resolve");
+    lscall!(@req code_lens, "textDocument/codeLens");
+    lscall!(@req code_lens_resolve, "codeLens/resolve");
+    lscall!(@req document_link, "textDocument/documentLink");
+    lscall!(@req document_link_resolve, "documentLink/resolve");
+    lscall!(@req folding_range, "textDocument/foldingRange");
+    lscall!(@req selection_range, "textDocument/selectionRange");
+    lscall!(@req document_color, "textDocument/documentColor");
+    lscall!(@req color_presentation, "textDocument/colorPresentation");
+    lscall!(@req prepare_call_hierarchy, "textDocument/prepareCallHierarchy");
+    lscall!(@req incoming_calls, "callHierarchy/incomingCalls");
+    lscall!(@req outgoing_calls, "callHierarchy/outgoingCalls");
+    lscall!(@req semantic_tokens_full, "textDocument/semanticTokens/full");
+    lscall!(@req semantic_tokens_range, "textDocument/semanticTokens/range");
+    lscall!(@req document_diagnostics, "textDocument/diagnostic");
+    lscall!(@req inlay_hint, "textDocument/inlayHint");
+    lscall!(@req inlay_hint_resolve, "inlayHint/resolve");
+    // lscall!(@req textDocument/applyEdit, "textDocument/applyEdit");
+    lscall!(@req range_formatting, "textDocument/rangeFormatting");
+    lscall!(@req on_type_formatting, "textDocument/onTypeFormatting");
+    lscall!(@req formatting, "textDocument/formatting");
+    lscall!(@req rename, "textDocument/rename");
+    lscall!(@req prepare_rename, "textDocument/prepareRename");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+    use tokio_core::reactor::Core;
+
+    fn progress(token: &str, value: Value) -> Parsed {
+        Ok(Message::notification("$/progress".to_string(), Some(json!({"token": token, "value": value}))))
+    }
+
+    #[test]
+    fn test_initialize_and_wait() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let to_client = remote.to_client.clone();
+        let received = test_util::serve(remote, &handle, |_req| Some(json!({"capabilities": {}})));
+        let client = RustAnalyzerClient::new(pipe, &handle);
+
+        to_client.unbounded_send(Ok(Message::request(
+            "window/workDoneProgress/create".to_string(),
+            Some(json!({"token": "rustAnalyzer/Indexing"})),
+        ))).unwrap();
+        for (token, value) in vec![
+            ("rustAnalyzer/Fetching", json!({"kind": "begin", "title": "Fetching"})),
+            ("rustAnalyzer/Fetching", json!({"kind": "end"})),
+            ("rustAnalyzer/Indexing", json!({"kind": "begin", "title": "Indexing", "percentage": 0})),
+            ("rustAnalyzer/Indexing", json!({"kind": "report", "message": "1/2 (core)", "percentage": 50})),
+            ("rustAnalyzer/Indexing", json!({"kind": "end"})),
+        ] {
+            to_client.unbounded_send(progress(token, value)).unwrap();
+        }
+
+        let (_client, resp) = core.run(client.initialize_and_wait(test_util::init_params())).unwrap();
+        assert!(resp.is_ok());
+        test_util::settle(&mut core);
+        // The progress token was accepted
+        assert!(received.borrow().iter().any(|msg| match *msg {
+            Message::Response(ref resp) => match resp.result {
+                Ok(Value::Null) => true,
+                _ => false,
+            },
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn test_progress_not_consumed() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (pipe, remote) = test_util::pipe();
+        let (collector, progress_rx) = ProgressCollector::new();
+        let _client = RustAnalyzerClient::with_notification_handler(pipe, collector, &handle);
+
+        remote.to_client.unbounded_send(progress("rustAnalyzer/Indexing", json!({"kind": "end"}))).unwrap();
+        let (params, _rest) = test_util::recv(&mut core, progress_rx);
+        assert_eq!(params.value, WorkDoneProgress::End { message: None });
+    }
+}
@@ -26,19 +26,143 @@
 
 use bytes::{BufMut, BytesMut};
 use jsonrpc::{BoundaryCodec, Message, Parsed};
+use jsonrpc::message::{from_slice, Broken};
+use serde::de::IgnoredAny;
+use serde_json::{self, Value};
 use tokio_io::codec::{Decoder, Encoder};
 
 use std::error::Error;
-use std::io::{Error as IoError, Result as IoResult, Read};
+use std::io::{Error as IoError, ErrorKind, Result as IoResult, Read};
 use std::str;
 
 use super::custom_err;
+#[cfg(feature = "compression")]
+use compression::ContentEncoding;
 
 /// A codec working with LSP messages.
 ///
 /// The implementation is just a simple wrapper around the
 /// `tokio_jsonrpc::BoundaryCodec` codec, and just adds/strips the header.
-pub struct LspCodec;
+///
+/// Use `LspCodec::builder()` to configure it.
+#[derive(Clone, Debug)]
+pub struct LspCodec {
+    /// Messages advertising a larger `Content-Length` are rejected.
+    max_len: Option<usize>,
+    /// Whether encoded messages include a `Content-Type` header.
+    emit_content_type: bool,
+    /// Whether header lines must be terminated by `\r\n`.
+    strict_crlf: bool,
+    /// Whether decoded messages must declare `"jsonrpc": "2.0"`.
+    strict_version: bool,
+    /// Whether encoded header lines end with a bare `\n`.
+    bare_newlines: bool,
+    /// How far into the buffer the end of the headers has already been
+    /// looked for, so that a slowly arriving message isn't scanned again
+    /// from the start on each read.
+    scanned: usize,
+    /// The message being decoded, once its headers have been parsed.
+    pending: Option<Pending>,
+    /// How encoded message bodies are compressed.
+    #[cfg(feature = "compression")]
+    compression: Option<ContentEncoding>,
+}
+
+/// The default limit on the size of a message body (16 MiB).
+const DEFAULT_MAX_LEN: usize = 16 * 1024 * 1024;
+
+/// The limit on the size of the headers of a message (8 KiB), so that a
+/// peer which never ends them can't fill up the buffer.
+const MAX_HEADER_LEN: usize = 8 * 1024;
+
+/// The `Content-Type` header written by the encoder when enabled.
+const CONTENT_TYPE: &'static str = "Content-Type: application/vscode-jsonrpc; charset=utf-8";
+
+impl LspCodec {
+    /// Create a codec with the default settings.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start configuring a codec, from the default settings.
+    pub fn builder() -> LspCodecBuilder {
+        LspCodecBuilder { codec: Self::default() }
+    }
+}
+
+impl Default for LspCodec {
+    fn default() -> Self {
+        LspCodec {
+            max_len: Some(DEFAULT_MAX_LEN),
+            emit_content_type: false,
+            strict_crlf: false,
+            strict_version: false,
+            bare_newlines: false,
+            scanned: 0,
+            pending: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+        }
+    }
+}
+
+/// Builds a configured `LspCodec`, see `LspCodec::builder`.
+#[derive(Clone, Debug)]
+pub struct LspCodecBuilder {
+    codec: LspCodec,
+}
+
+impl LspCodecBuilder {
+    /// Reject message bodies longer than `max_len` bytes, or accept any
+    /// length for `None` (16 MiB by default).
+    pub fn max_len(mut self, max_len: Option<usize>) -> Self {
+        self.codec.max_len = max_len;
+        self
+    }
+
+    /// Set whether encoded messages include a `Content-Type` header (off by default).
+    pub fn emit_content_type(mut self, emit: bool) -> Self {
+        self.codec.emit_content_type = emit;
+        self
+    }
+
+    /// Set whether decoding rejects header lines terminated by a bare `\n`
+    /// (off by default).
+    pub fn strict_crlf(mut self, strict: bool) -> Self {
+        self.codec.strict_crlf = strict;
+        self
+    }
+
+    /// Set whether encoded header lines end with a bare `\n` rather than
+    /// `\r\n`, for tools which don't handle the latter (off by default).
+    ///
+    /// This is not spec compliant.
+    pub fn bare_newlines(mut self, bare: bool) -> Self {
+        self.codec.bare_newlines = bare;
+        self
+    }
+
+    /// Set whether decoding rejects messages which do not declare
+    /// `"jsonrpc": "2.0"` (off by default).
+    pub fn strict_version(mut self, strict: bool) -> Self {
+        self.codec.strict_version = strict;
+        self
+    }
+
+    /// Compress the bodies of encoded messages, or leave them uncompressed
+    /// for `None` (the default).
+    ///
+    /// Compressed bodies are always accepted when decoding.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, encoding: Option<ContentEncoding>) -> Self {
+        self.codec.compression = encoding;
+        self
+    }
+
+    pub fn build(self) -> LspCodec {
+        self.codec
+    }
+}
 
 impl Encoder for LspCodec {
     type Item = Message;
@@ -47,85 +171,288 @@ impl Encoder for LspCodec {
         let mut body = BytesMut::new();
         let mut codec = BoundaryCodec;
         codec.encode(msg, &mut body)?;
-        let req_len = body.len();
-        buf.reserve(20 + req_len + req_len.to_string().len());
-        buf.put(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes());
+        #[cfg(feature = "compression")]
+        let body = match self.compression {
+            Some(encoding) => BytesMut::from(encoding.compress(&body)?),
+            None => body,
+        };
+        self.write_headers(body.len(), buf);
+        buf.reserve(body.len());
         buf.put(body);
         Ok(())
     }
 }
 
+impl LspCodec {
+    /// Writes the headers for a body of `len` bytes, including the empty
+    /// line ending them.
+    fn write_headers(&self, len: usize, buf: &mut BytesMut) {
+        let newline = if self.bare_newlines { "\n" } else { "\r\n" };
+        let mut headers = format!("Content-Length: {}{}", len, newline);
+        if self.emit_content_type {
+            headers.push_str(CONTENT_TYPE);
+            headers.push_str(newline);
+        }
+        #[cfg(feature = "compression")]
+        {
+            if let Some(encoding) = self.compression {
+                headers.push_str(&format!("Content-Encoding: {}{}", encoding.name(), newline));
+            }
+        }
+        headers.push_str(newline);
+        buf.reserve(headers.len());
+        buf.put(headers.as_bytes());
+    }
+}
+
 impl Decoder for LspCodec {
     type Item = Parsed;
     type Error = IoError;
     fn decode(&mut self, src: &mut BytesMut) -> IoResult<Option<Parsed>> {
-        let mut content_length: Option<usize> = None;
-        let mut pos = 0;
-
-        if let Some(i) = src.windows(4).position(|b| b == b"\r\n\r\n") {
-            let mut header_buf = src.split_to(i + 4);
-            let mut buffer = String::new();
-            for (idx, _) in header_buf.iter().enumerate().filter(|(_idx, &b)| b == b'\n') {
-                buffer.clear();
-                (&header_buf[pos..idx]).read_to_string(&mut buffer)?;
-                match &buffer {
-                    s if s.trim().len() == 0 => { break }, // empty line is end of headers
-                    s => {
-                        match parse_header(s)? {
-                            LspHeader::ContentLength(len) => content_length = Some(len),
-                            LspHeader::ContentType => (), // utf-8 only currently allowed value
-                        };
-                    }
-                };
-                pos = idx;
-            }
-
-            match content_length {
-                Some(l) => {
-                    if src.len() < l {
-                        // Return the header to the buffer
-                        header_buf.unsplit(src.take());
-                        *src = header_buf;
-                        Ok(None)
-                    } else {
-                        let mut body = src.split_to(l);
-                        let mut codec = BoundaryCodec;
-                        codec.decode(&mut body)
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => match find_header_end(src, self.scanned) {
+                Some(header_len) if header_len > MAX_HEADER_LEN => return Err(headers_too_long()),
+                Some(header_len) => {
+                    self.scanned = 0;
+                    let pending = self.parse_headers(&src[..header_len])?;
+                    if self.max_len.map_or(false, |max| pending.content_length > max) {
+                        return Err(custom_err(&format!("Message of {} bytes exceeds the maximum length", pending.content_length)));
                     }
+                    pending
                 },
+                None if src.len() > MAX_HEADER_LEN => return Err(headers_too_long()),
                 None => {
-                    Err(custom_err("Malformed header, missing Content-Length"))
+                    // The end of the headers may straddle the bytes still to come
+                    self.scanned = src.len().saturating_sub(2);
+                    return Ok(None);
+                },
+            },
+        };
+
+        // Only a limit of `None` lets through a length this large
+        let message_len = match pending.header_len.checked_add(pending.content_length) {
+            Some(message_len) => message_len,
+            None => return Err(IoError::new(ErrorKind::InvalidData,
+                format!("Content-Length of {} bytes is too large", pending.content_length))),
+        };
+        if src.len() < message_len {
+            // The header stays in the buffer until the whole body has arrived
+            self.pending = Some(pending);
+            return Ok(None);
+        }
+        src.split_to(pending.header_len);
+        let body = src.split_to(pending.content_length);
+        #[cfg(feature = "compression")]
+        let body = match pending.encoding {
+            Some(encoding) => encoding.decompress(&body, self.max_len)?,
+            None => body,
+        };
+        let body = transcode(body, pending.charset)?;
+        let parsed = from_slice(&body);
+        // Only look into why a message is broken once it turns out to be
+        match parsed {
+            Err(Broken::SyntaxError(_)) => check_complete(&body, pending.content_length)?,
+            Err(Broken::Unmatched(ref message)) if self.strict_version => check_version(message)?,
+            Ok(Message::Batch(ref messages)) if self.strict_version => {
+                for message in messages {
+                    if let Message::UnmatchedSub(ref message) = *message {
+                        check_version(message)?;
+                    }
                 }
+            },
+            _ => (),
+        }
+        Ok(Some(parsed))
+    }
+}
+
+impl LspCodec {
+    /// Parses the header block (including the empty line).
+    fn parse_headers(&self, header: &[u8]) -> IoResult<Pending> {
+        let mut content_length: Option<usize> = None;
+        let mut charset = Charset::Utf8;
+        #[cfg(feature = "compression")]
+        let mut encoding = None;
+        let mut pos = 0;
+        let mut buffer = String::new();
+        for (idx, _) in header.iter().enumerate().filter(|(_idx, &b)| b == b'\n') {
+            buffer.clear();
+            (&header[pos..idx]).read_to_string(&mut buffer)?;
+            if self.strict_crlf && !buffer.ends_with('\r') {
+                return Err(custom_err("Malformed header, line not terminated by CRLF"));
             }
-        } else {
-            Ok(None)
+            match &buffer {
+                s if s.trim().len() == 0 => { break }, // empty line is end of headers
+                s => {
+                    match parse_header(s)? {
+                        LspHeader::ContentLength(len) => content_length = Some(len),
+                        LspHeader::ContentType(cs) => charset = cs,
+                        #[cfg(feature = "compression")]
+                        LspHeader::ContentEncoding(e) => encoding = Some(e),
+                    };
+                }
+            };
+            pos = idx + 1; // skip past the newline
+        }
+        match content_length {
+            Some(content_length) => Ok(Pending {
+                header_len: header.len(),
+                content_length,
+                charset,
+                #[cfg(feature = "compression")]
+                encoding,
+            }),
+            None => Err(custom_err("Malformed header, missing Content-Length")),
         }
     }
 }
 
+/// The decoder's progress through a message whose headers have been parsed,
+/// but whose body has not fully arrived yet.
+#[derive(Clone, Copy, Debug)]
+struct Pending {
+    header_len: usize,
+    content_length: usize,
+    charset: Charset,
+    #[cfg(feature = "compression")]
+    encoding: Option<ContentEncoding>,
+}
+
 
 #[derive(Debug, PartialEq)]
 /// A message header, as described in the Language Server Protocol specification.
 enum LspHeader {
-    ContentType,
+    ContentType(Charset),
     ContentLength(usize),
+    /// Not part of the spec, see `compression`.
+    #[cfg(feature = "compression")]
+    ContentEncoding(ContentEncoding),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+/// The character encodings accepted for a message body.
+enum Charset {
+    Utf8,
+    /// UTF-16 with the endianness given by a byte order mark (big endian if absent).
+    Utf16,
+    Utf16Le,
+    Utf16Be,
 }
 
 const HEADER_CONTENT_LENGTH: &'static [u8] = b"content-length";
 const HEADER_CONTENT_TYPE: &'static [u8] = b"content-type";
+#[cfg(feature = "compression")]
+const HEADER_CONTENT_ENCODING: &'static [u8] = b"content-encoding";
+
+
+/// Finds the end of the headers, returning the length of the header block
+/// including the empty line.
+///
+/// Lines may be terminated by either `\r\n` or a bare `\n`. Bytes before
+/// `from` are known not to start the empty line, and are skipped.
+fn find_header_end(src: &[u8], from: usize) -> Option<usize> {
+    src.iter()
+        .enumerate()
+        .skip(from)
+        .filter(|&(_, &b)| b == b'\n')
+        .filter_map(|(i, _)| match (src.get(i + 1), src.get(i + 2)) {
+            (Some(&b'\n'), _) => Some(i + 2),
+            (Some(&b'\r'), Some(&b'\n')) => Some(i + 3),
+            _ => None,
+        })
+        .next()
+}
 
+fn headers_too_long() -> IoError {
+    IoError::new(ErrorKind::InvalidData, format!("Message headers exceed {} bytes", MAX_HEADER_LEN))
+}
 
 /// Given a header string, attempts to extract and validate the name and value parts.
+///
+/// The name and value are split on the first colon, with any whitespace
+/// around either ignored.
 fn parse_header(s: &str) -> IoResult<LspHeader> {
-    let split: Vec<String> = s.split(": ").map(|s| s.trim().to_lowercase()).collect();
+    let split: Vec<String> = s.splitn(2, ':').map(|s| s.trim().to_lowercase()).collect();
     if split.len() != 2 { return Err(custom_err(&format!("malformed header: {}", s))) }
     match split[0].as_ref() {
-        HEADER_CONTENT_TYPE => Ok(LspHeader::ContentType),
+        HEADER_CONTENT_TYPE => Ok(LspHeader::ContentType(parse_charset(&split[1])?)),
         HEADER_CONTENT_LENGTH => Ok(LspHeader::ContentLength(usize::from_str_radix(&split[1], 10).map_err(|e| custom_err(e.description()))?)),
+        #[cfg(feature = "compression")]
+        HEADER_CONTENT_ENCODING => Ok(LspHeader::ContentEncoding(ContentEncoding::parse(&split[1])?)),
         _ => Err(custom_err(&format!("Unknown header: {}", s))),
     }
 }
 
+/// Extracts the charset parameter of a `Content-Type` value, defaulting to utf-8.
+fn parse_charset(content_type: &str) -> IoResult<Charset> {
+    let charset = content_type.split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let mut kv = param.splitn(2, '=');
+            match (kv.next().map(str::trim), kv.next()) {
+                (Some("charset"), Some(v)) => Some(v.trim().trim_matches('"')),
+                _ => None,
+            }
+        })
+        .next();
+    match charset {
+        None | Some("utf-8") | Some("utf8") => Ok(Charset::Utf8),
+        Some("utf-16") => Ok(Charset::Utf16),
+        Some("utf-16le") => Ok(Charset::Utf16Le),
+        Some("utf-16be") => Ok(Charset::Utf16Be),
+        Some(other) => Err(custom_err(&format!("Unsupported charset: {}", other))),
+    }
+}
+
+/// Converts a message body in the given charset to utf-8.
+fn transcode(body: BytesMut, charset: Charset) -> IoResult<BytesMut> {
+    let (big_endian, bytes) = match charset {
+        Charset::Utf8 => return Ok(body),
+        Charset::Utf16Le => (false, &body[..]),
+        Charset::Utf16Be => (true, &body[..]),
+        Charset::Utf16 if body.starts_with(&[0xFF, 0xFE]) => (false, &body[2..]),
+        Charset::Utf16 if body.starts_with(&[0xFE, 0xFF]) => (true, &body[2..]),
+        Charset::Utf16 => (true, &body[..]),
+    };
+    if bytes.len() % 2 != 0 {
+        return Err(custom_err("Malformed utf-16 body, odd number of bytes"));
+    }
+    let units: Vec<u16> = bytes.chunks(2).map(|pair| {
+        if big_endian {
+            (pair[0] as u16) << 8 | pair[1] as u16
+        } else {
+            (pair[1] as u16) << 8 | pair[0] as u16
+        }
+    }).collect();
+    let text = String::from_utf16(&units).map_err(|e| custom_err(&format!("Malformed utf-16 body: {}", e)))?;
+    Ok(BytesMut::from(text.into_bytes()))
+}
+
+/// Checks whether `body`, which failed to parse, is cut off in the middle
+/// of the JSON, which happens when a server declares a `Content-Length`
+/// shorter than the body (e.g. by counting characters rather than bytes).
+fn check_complete(body: &[u8], content_length: usize) -> IoResult<()> {
+    match serde_json::from_slice::<IgnoredAny>(body) {
+        Err(ref e) if e.is_eof() => Err(custom_err(&format!(
+            "Message body is incomplete JSON after consuming the {} bytes of its Content-Length, \
+             the server may have miscounted the length", content_length))),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `message`, which jsonrpc could not make sense of, declares
+/// version 2.0 of jsonrpc.
+///
+/// Messages which do are left for jsonrpc to report.
+fn check_version(message: &Value) -> IoResult<()> {
+    match message.get("jsonrpc") {
+        Some(&Value::String(ref version)) if version == "2.0" => Ok(()),
+        Some(version) => Err(custom_err(&format!("Unsupported jsonrpc version {}, expected \"2.0\"", version))),
+        None => Err(custom_err("Message is missing the \"jsonrpc\": \"2.0\" field")),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,6 +462,153 @@ mod tests {
     fn test_parse_header() {
         let header = "Content-Length: 132";
         assert_eq!(parse_header(header).ok(), Some(LspHeader::ContentLength(132)));
+        for header in vec!["Content-Length:132", "Content-Length :  132", "content-length: 132 \r"] {
+            assert_eq!(parse_header(header).ok(), Some(LspHeader::ContentLength(132)));
+        }
+        assert!(parse_header("Content-Length 132").is_err());
+    }
+
+    #[test]
+    fn test_parse_header_value_with_colon() {
+        let header = "Content-Type: application/vscode-jsonrpc; note=\"a: b\"; charset=utf-8";
+        assert_eq!(parse_header(header).ok(), Some(LspHeader::ContentType(Charset::Utf8)));
+    }
+
+    #[test]
+    fn test_parse_charset() {
+        let header = "Content-Type: application/vscode-jsonrpc; charset=utf-16";
+        assert_eq!(parse_header(header).ok(), Some(LspHeader::ContentType(Charset::Utf16)));
+        let header = "Content-Type: application/vscode-jsonrpc; charset=latin-1";
+        assert!(parse_header(header).is_err());
+    }
+
+    #[test]
+    fn test_utf16_message() {
+        let json = "{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"tést\"}";
+        let msg = jsonrpc::message::from_str(json).unwrap();
+        let body: Vec<u8> = json.encode_utf16().flat_map(|u| vec![u as u8, (u >> 8) as u8]).collect();
+
+        let mut bytes = BytesMut::new();
+        bytes.extend_from_slice(format!("Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=utf-16le\r\n\r\n", body.len()).as_bytes());
+        bytes.extend_from_slice(&body);
+
+        let mut codec = LspCodec::default();
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg));
+    }
+
+    #[test]
+    fn test_max_len() {
+        let mut codec = LspCodec::builder().max_len(Some(1024)).build();
+        let mut bytes = BytesMut::from(&b"Content-Length: 99999999999\r\n\r\n{"[..]);
+        assert!(codec.decode(&mut bytes).is_err());
+
+        let mut codec = LspCodec::default();
+        let mut bytes = BytesMut::from(&b"Content-Length: 99999999999\r\n\r\n{"[..]);
+        assert!(codec.decode(&mut bytes).is_err());
+
+        // The length overflows once the headers are added
+        let mut codec = LspCodec::builder().max_len(None).build();
+        let mut bytes = BytesMut::from(&b"Content-Length: 18446744073709551615\r\n\r\n{"[..]);
+        assert_eq!(codec.decode(&mut bytes).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_max_header_len() {
+        let mut codec = LspCodec::default();
+        let mut bytes = BytesMut::from(&b"Content-Length: 2\r\n"[..]);
+        while bytes.len() <= MAX_HEADER_LEN {
+            bytes.extend_from_slice(b"Content-Type: application/vscode-jsonrpc\r\n");
+            if bytes.len() <= MAX_HEADER_LEN {
+                assert!(codec.decode(&mut bytes).unwrap().is_none());
+            }
+        }
+        assert_eq!(codec.decode(&mut bytes).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_write_headers() {
+        let mut buf = BytesMut::new();
+        LspCodec::default().write_headers(132, &mut buf);
+        assert_eq!(&buf[..], &b"Content-Length: 132\r\n\r\n"[..]);
+
+        let mut buf = BytesMut::new();
+        LspCodec::builder().bare_newlines(true).emit_content_type(true).build().write_headers(7, &mut buf);
+        assert_eq!(&buf[..], &b"Content-Length: 7\nContent-Type: application/vscode-jsonrpc; charset=utf-8\n\n"[..]);
+    }
+
+    #[test]
+    fn test_encode_content_type() {
+        let msg = jsonrpc::message::from_str("{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}").unwrap();
+        let mut body = BytesMut::new();
+        BoundaryCodec.encode(msg.clone(), &mut body).unwrap();
+
+        let mut expected = format!("Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n", body.len()).into_bytes();
+        expected.extend_from_slice(&body);
+        let mut buf = BytesMut::new();
+        LspCodec::builder().emit_content_type(true).build().encode(msg.clone(), &mut buf).unwrap();
+        assert_eq!(&buf[..], &expected[..]);
+
+        let mut expected = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        expected.extend_from_slice(&body);
+        let mut buf = BytesMut::new();
+        LspCodec::default().encode(msg, &mut buf).unwrap();
+        assert_eq!(&buf[..], &expected[..]);
+    }
+
+    #[test]
+    fn test_builder() {
+        let msg = jsonrpc::message::from_str("{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}").unwrap();
+        let json = "{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}";
+
+        let mut codec = LspCodec::builder().max_len(Some(16)).build();
+        let mut bytes = BytesMut::from(format!("Content-Length: 43\r\n\r\n{}", json).as_bytes());
+        assert!(codec.decode(&mut bytes).is_err());
+        let mut codec = LspCodec::builder().max_len(None).build();
+        let mut bytes = BytesMut::from(format!("Content-Length: 43\r\n\r\n{}", json).as_bytes());
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+
+        let mut buf = BytesMut::new();
+        LspCodec::builder().emit_content_type(true).build().encode(msg.clone(), &mut buf).unwrap();
+        assert!(buf.starts_with(b"Content-Length: 43\r\nContent-Type: "));
+
+        let mut codec = LspCodec::builder().strict_crlf(true).build();
+        let mut bytes = BytesMut::from(format!("Content-Length: 43\r\n\r\n{}", json).as_bytes());
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+        let mut bytes = BytesMut::from(format!("Content-Length: 43\n\n{}", json).as_bytes());
+        assert!(codec.decode(&mut bytes).is_err());
+        let mut bytes = BytesMut::from(format!("Content-Length: 43\n\n{}", json).as_bytes());
+        assert_eq!(LspCodec::default().decode(&mut bytes).unwrap().unwrap(), Ok(msg));
+    }
+
+    #[test]
+    fn test_strict_version() {
+        let json = "{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}";
+        let mut codec = LspCodec::builder().strict_version(true).build();
+        let mut bytes = BytesMut::from(format!("Content-Length: 43\r\n\r\n{}", json).as_bytes());
+        assert!(codec.decode(&mut bytes).unwrap().is_some());
+
+        let batch = "[{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}, {\"id\": 2,\"method\": \"test\"}]";
+        for json in vec!["{\"id\": 1,\"method\": \"test\"}", "{\"jsonrpc\": \"1.0\",\"id\": 1,\"method\": \"test\"}", batch] {
+            let mut bytes = BytesMut::from(format!("Content-Length: {}\r\n\r\n{}", json.len(), json).as_bytes());
+            assert!(codec.decode(&mut bytes).is_err());
+        }
+
+        let json = "{\"id\": 1,\"method\": \"test\"}";
+        let mut bytes = BytesMut::from(format!("Content-Length: {}\r\n\r\n{}", json.len(), json).as_bytes());
+        assert!(LspCodec::default().decode(&mut bytes).is_ok());
+    }
+
+    #[test]
+    fn test_length_too_short() {
+        // Counting characters instead of bytes is one short for "é"
+        let json = "{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"tést\"}";
+        let mut codec = LspCodec::default();
+        let mut bytes = BytesMut::from(format!("Content-Length: {}\r\n\r\n{}", json.chars().count(), json).as_bytes());
+        let err = codec.decode(&mut bytes).unwrap_err();
+        assert!(err.to_string().contains("43 bytes"), "unexpected error: {}", err);
+
+        let mut bytes = BytesMut::from(format!("Content-Length: {}\r\n\r\n{}", json.len(), json).as_bytes());
+        assert!(codec.decode(&mut bytes).unwrap().is_some());
     }
 
     #[test]
@@ -144,7 +618,7 @@ mod tests {
                         "Content-Length: 43\r\n\r\n{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}", 
                         "Content-Length: 43\n\rContent-Type: utf-8\r\n\r\n{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}");
 
-        let mut codec = LspCodec;
+        let mut codec = LspCodec::default();
         let mut bytes = BytesMut::new();
         for inp in inps {
             // let mut reader = BufReader::new(inp.as_bytes());
@@ -158,13 +632,123 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_multiple_headers() {
+        let msg = jsonrpc::message::from_str("{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}").unwrap();
+        let inps = vec!("Content-Length: 43\r\nContent-Type: application/vscode-jsonrpc; charset=utf-8\r\n\r\n{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}",
+                        "Content-Type: application/vscode-jsonrpc; charset=utf-8\r\nContent-Length: 43\r\n\r\n{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}");
+
+        let mut codec = LspCodec::default();
+        for inp in inps {
+            let mut bytes = BytesMut::from(inp.as_bytes());
+            assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+            assert!(bytes.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_header_separators() {
+        assert_eq!(find_header_end(b"Content-Length: 2\r\n\r\n{}", 0), Some(21));
+        assert_eq!(find_header_end(b"Content-Length: 2\n\n{}", 0), Some(19));
+        assert_eq!(find_header_end(b"Content-Length: 2\r\n", 0), None);
+        assert_eq!(find_header_end(b"Content-Length: 2\r\n\r\n{}", 17), Some(21));
+
+        let msg = jsonrpc::message::from_str("{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}").unwrap();
+        let inps = vec!("Content-Length: 43\r\nContent-Type: utf-8\r\n\r\n{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}",
+                        "Content-Length: 43\nContent-Type: utf-8\n\n{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}");
+        let mut codec = LspCodec::default();
+        for inp in inps {
+            let mut bytes = BytesMut::from(inp.as_bytes());
+            assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+        }
+    }
+
+    #[test]
+    fn test_buffered_messages() {
+        let json = "{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}";
+        let msg = jsonrpc::message::from_str(json).unwrap();
+        let framed = format!("Content-Length: 43\r\n\r\n{}", json);
+        let (start, rest) = framed.split_at(30);
+
+        let mut codec = LspCodec::default();
+        let mut bytes = BytesMut::from(format!("{}{}{}", framed, framed, start).as_bytes());
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+        assert!(codec.decode(&mut bytes).unwrap().is_none());
+        assert_eq!(&bytes[..], start.as_bytes());
+
+        // The retained bytes are picked up again once the rest arrives
+        bytes.extend_from_slice(rest.as_bytes());
+        assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg));
+        assert!(bytes.is_empty());
+
+        // Likewise when the cut falls inside the header
+        let (start, rest) = framed.split_at(10);
+        let mut bytes = BytesMut::from(format!("{}{}", framed, start).as_bytes());
+        assert!(codec.decode(&mut bytes).unwrap().is_some());
+        assert!(codec.decode(&mut bytes).unwrap().is_none());
+        assert_eq!(&bytes[..], start.as_bytes());
+        bytes.extend_from_slice(rest.as_bytes());
+        assert!(codec.decode(&mut bytes).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_byte_at_a_time() {
+        let json = format!("{{\"jsonrpc\": \"2.0\",\"method\": \"test\",\"params\": {{\"text\": \"{}\"}}}}", "x".repeat(100_000));
+        let msg = jsonrpc::message::from_str(&json).unwrap();
+        let framed = format!("Content-Length: {}\r\nContent-Type: utf-8\r\n\r\n{}", json.len(), json);
+        let header_len = framed.len() - json.len();
+
+        let mut codec = LspCodec::default();
+        let mut bytes = BytesMut::new();
+        for (idx, b) in framed.bytes().enumerate() {
+            bytes.extend_from_slice(&[b]);
+            if idx + 1 < framed.len() {
+                assert!(codec.decode(&mut bytes).unwrap().is_none());
+                // The headers are only parsed once
+                assert_eq!(codec.pending.is_some(), idx + 1 >= header_len);
+            } else {
+                assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+            }
+        }
+        assert!(bytes.is_empty());
+        assert!(codec.pending.is_none());
+        assert_eq!(codec.scanned, 0);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compression() {
+        let json = format!("{{\"jsonrpc\": \"2.0\",\"method\": \"test\",\"params\": {{\"text\": \"{}\"}}}}", "fn main() {}\\n".repeat(10_000));
+        let msg = jsonrpc::message::from_str(&json).unwrap();
+
+        for encoding in vec![ContentEncoding::Gzip, ContentEncoding::Deflate] {
+            let mut codec = LspCodec::builder().compression(Some(encoding)).build();
+            let mut bytes = BytesMut::new();
+            codec.encode(msg.clone(), &mut bytes).unwrap();
+            let header = format!("Content-Encoding: {}\r\n", encoding.name());
+            assert!(bytes.windows(header.len()).any(|window| window == header.as_bytes()));
+            assert!(bytes.len() < json.len() / 10);
+
+            // Compressed bodies are accepted whether or not the decoder compresses
+            let mut copy = bytes.clone();
+            assert_eq!(codec.decode(&mut bytes).unwrap().unwrap(), Ok(msg.clone()));
+            assert_eq!(LspCodec::default().decode(&mut copy).unwrap().unwrap(), Ok(msg.clone()));
+        }
+
+        // The limit applies to the decompressed body too
+        let mut bytes = BytesMut::new();
+        LspCodec::builder().compression(Some(ContentEncoding::Gzip)).build().encode(msg, &mut bytes).unwrap();
+        assert!(LspCodec::builder().max_len(Some(json.len() / 2)).build().decode(&mut bytes).is_err());
+    }
+
     #[test]
     fn test_partial_message() {
         let msg = jsonrpc::message::from_str("{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}").unwrap();
         let inps = vec!("Content-Length: 43\r\n\r\n",
                         "{\"jsonrpc\": \"2.0\",\"id\": 1,\"method\": \"test\"}");
         let mut result = None;
-        let mut codec = LspCodec;
+        let mut codec = LspCodec::default();
         let mut bytes = BytesMut::new();
         for inp in inps {
             // let mut reader = BufReader::new(inp.as_bytes());
@@ -0,0 +1,76 @@
+//! Compression of message bodies, for connections to remote servers over
+//! slow networks (enabled by the `compression` feature).
+//!
+//! The compression of a body is announced with a `Content-Encoding` header.
+//! This is not part of the LSP spec, so both ends must be set up to use it,
+//! see `LspCodecBuilder::compression`.
+
+use bytes::BytesMut;
+use flate2::Compression;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use flate2::write::{DeflateEncoder, GzEncoder};
+
+use std::io::{Read, Result as IoResult, Write};
+
+use super::custom_err;
+
+/// The compressions supported for message bodies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Parse the value of a `Content-Encoding` header.
+    pub(crate) fn parse(value: &str) -> IoResult<Self> {
+        match value {
+            "gzip" => Ok(ContentEncoding::Gzip),
+            "deflate" => Ok(ContentEncoding::Deflate),
+            other => Err(custom_err(&format!("Unsupported Content-Encoding: {}", other))),
+        }
+    }
+
+    /// The value of the `Content-Encoding` header.
+    pub(crate) fn name(&self) -> &'static str {
+        match *self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    pub(crate) fn compress(&self, body: &[u8]) -> IoResult<Vec<u8>> {
+        match *self {
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            },
+            ContentEncoding::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            },
+        }
+    }
+
+    /// Decompress `body`, failing if it would be longer than `max_len`.
+    pub(crate) fn decompress(&self, body: &[u8], max_len: Option<usize>) -> IoResult<BytesMut> {
+        match *self {
+            ContentEncoding::Gzip => read_limited(GzDecoder::new(body), max_len),
+            ContentEncoding::Deflate => read_limited(DeflateDecoder::new(body), max_len),
+        }
+    }
+}
+
+/// Read all of `reader`, without reading more than one byte past `max_len`
+/// so that a small body can't expand without bounds.
+fn read_limited<R: Read>(reader: R, max_len: Option<usize>) -> IoResult<BytesMut> {
+    let limit = max_len.map_or(u64::max_value(), |max| max as u64 + 1);
+    let mut body = Vec::new();
+    reader.take(limit).read_to_end(&mut body)?;
+    if max_len.map_or(false, |max| body.len() > max) {
+        return Err(custom_err("Decompressed message exceeds the maximum length"));
+    }
+    Ok(BytesMut::from(body))
+}
@@ -0,0 +1,97 @@
+//! The error type returned by LSP clients.
+
+use jsonrpc::message::RpcError;
+use serde_json::{self, Value};
+
+use std::error::Error;
+use std::fmt;
+use std::io::Error as IoError;
+use std::process::ExitStatus;
+
+/// The ways in which a request or notification can fail.
+#[derive(Debug)]
+pub enum LspError {
+    /// The parameters could not be serialized.
+    Serialize(serde_json::Error),
+    /// The parameters were rejected before sending.
+    InvalidParams(String),
+    /// The result sent by the server did not have the expected shape, along
+    /// with the result as sent where it was kept.
+    ///
+    /// Only the result of `initialize` is kept; other results are converted
+    /// in place, to avoid holding a copy of large responses.
+    Deserialize(serde_json::Error, Option<Value>),
+    /// An error in the underlying connection.
+    Transport(IoError),
+    /// The server did not answer with a response.
+    MissingResponse,
+    /// The connection closed before the server answered.
+    ConnectionClosed,
+    /// The server answered with an error.
+    ServerError(RpcError),
+    /// No response arrived in time.
+    Timeout,
+    /// The server did not announce support for the method.
+    Unsupported(String),
+    /// Too many notifications are waiting to be sent; try again once the
+    /// connection has caught up.
+    QueueFull,
+    /// A file to send to the server could not be read.
+    File(IoError),
+    /// The server process did not exit cleanly after `shutdown` and `exit`,
+    /// or exited while a request was waiting for its response.
+    UnexpectedExit(ExitStatus),
+}
+
+impl fmt::Display for LspError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LspError::Serialize(ref e) => write!(f, "failed to serialize parameters: {}", e),
+            LspError::InvalidParams(ref reason) => write!(f, "invalid parameters: {}", reason),
+            LspError::Deserialize(ref e, Some(ref result)) => write!(f, "failed to deserialize result {}: {}", result, e),
+            LspError::Deserialize(ref e, None) => write!(f, "failed to deserialize result: {}", e),
+            LspError::Transport(ref e) => write!(f, "transport error: {}", e),
+            LspError::MissingResponse => write!(f, "expected a response value"),
+            LspError::ConnectionClosed => write!(f, "the connection closed before a response arrived"),
+            LspError::ServerError(ref e) => write!(f, "server responded with error {}: {}", e.code, e.message),
+            LspError::Timeout => write!(f, "timed out waiting for a response"),
+            LspError::Unsupported(ref method) => write!(f, "the server does not support {}", method),
+            LspError::QueueFull => write!(f, "too many notifications are waiting to be sent"),
+            LspError::File(ref e) => write!(f, "failed to read file: {}", e),
+            LspError::UnexpectedExit(ref status) => write!(f, "the server exited with {}", status),
+        }
+    }
+}
+
+impl Error for LspError {
+    fn description(&self) -> &str {
+        match *self {
+            LspError::Serialize(_) => "failed to serialize parameters",
+            LspError::InvalidParams(_) => "invalid parameters",
+            LspError::Deserialize(..) => "failed to deserialize result",
+            LspError::Transport(ref e) => e.description(),
+            LspError::MissingResponse => "expected a response value",
+            LspError::ConnectionClosed => "the connection closed before a response arrived",
+            LspError::ServerError(_) => "server responded with an error",
+            LspError::Timeout => "timed out waiting for a response",
+            LspError::Unsupported(_) => "the server does not support the method",
+            LspError::QueueFull => "too many notifications are waiting to be sent",
+            LspError::File(_) => "failed to read file",
+            LspError::UnexpectedExit(_) => "the server did not exit cleanly",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            LspError::Serialize(ref e) | LspError::Deserialize(ref e, _) => Some(e),
+            LspError::Transport(ref e) | LspError::File(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for LspError {
+    fn from(e: IoError) -> Self {
+        LspError::Transport(e)
+    }
+}
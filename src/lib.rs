@@ -8,26 +8,53 @@
 //! tokio_jsonrpc codec for the body.
 
 extern crate bytes;
+#[cfg(feature = "compression")]
+extern crate flate2;
 extern crate futures;
 extern crate languageserver_types as ls_types;
+#[macro_use]
+extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+#[cfg_attr(test, macro_use)]
 extern crate serde_json;
 extern crate tokio;
 extern crate tokio_io;
 extern crate tokio_core;
+extern crate tokio_process;
 extern crate tokio_jsonrpc as jsonrpc;
+extern crate url;
+
 
+// First, so that the `request!` macro is available to the other modules
+#[macro_use]
+pub mod types;
 
 pub mod client;
 mod codec;
+#[cfg(feature = "compression")]
+mod compression;
+mod error;
 mod lsp;
-// pub mod sync;
+pub mod params;
+pub mod position;
+pub mod server;
+pub mod sync;
+#[cfg(test)]
+mod test_util;
+pub mod transport;
+pub mod uri;
 
-pub use client::Client;
-pub use codec::LspCodec;
-pub use lsp::LspClient;
+pub use client::{Client, RemoteState};
+pub use codec::{LspCodec, LspCodecBuilder};
+#[cfg(feature = "compression")]
+pub use compression::ContentEncoding;
+pub use error::LspError;
+pub use lsp::{InitializeOptions, LspClient, ResultOrError, WithError};
+pub use params::{ClientInfo, DidChangeBuilder, FormattingOptionsExt, InitializeParamsBuilder};
+pub use position::{PositionEncoding, PositionMapper};
+pub use server::{LspServer, ServerAdapter};
 
 use std::io::{Error as IoError, ErrorKind};
 
@@ -7,42 +7,74 @@ use futures::{future, Future};
 use ls_types::*;
 use ls_types::notification::Notification;
 use ls_types::request::Request;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
-use std::io::Error;
+use std::io::Error as IoError;
+use std::marker::PhantomData;
 
 macro_rules! lsdef {
     (@req $fn_name:ident, $name:tt) => {
-        fn $fn_name(&mut self, params: <lsp_request!($name) as Request>::Params) -> Box<Future<Item=<lsp_request!($name) as Request>::Result, Error=Error>> {
-            Box::new(future::err(custom_err("Not implemented")))
+        fn $fn_name(&mut self, params: <request!($name) as Request>::Params) -> Box<Future<Item=<request!($name) as Request>::Result, Error=Self::Error>> {
+            Box::new(future::err(<Self::Error as From<IoError>>::from(custom_err("Not implemented"))))
         }
     };
     (@notify $fn_name:ident, $name:tt) => {
-        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), Error> {
-            Err(custom_err("Not implemented"))
+        fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), Self::Error> {
+            Err(<Self::Error as From<IoError>>::from(custom_err("Not implemented")))
         }
     };
 }
 
-#[derive(Deserialize)]
+/// The result of a request which reports some failures in its result rather
+/// than as an error response, such as `initialize`.
+///
+/// The two shapes are told apart when deserializing, trying `T` first.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(untagged)]
-pub(crate) enum InitializeOptions {
-    Result(InitializeResult),
-    Error(InitializeError),
+pub enum ResultOrError<T, E> {
+    Result(T),
+    Error(E),
 }
 
-impl Request for InitializeOptions {
-    type Params = <lsp_request!("initialize") as Request>::Params;
-    type Result = Self;
-    const METHOD: &'static str = "initialize";
+impl<T, E> ResultOrError<T, E> {
+    pub fn into_result(self) -> Result<T, E> {
+        match self {
+            ResultOrError::Result(r) => Ok(r),
+            ResultOrError::Error(e) => Err(e),
+        }
+    }
+}
+
+/// The request `Req`, with a result which may instead be the error `E`.
+///
+/// For example `client.call::<WithError<MyRequest, MyError>>(params)`.
+pub struct WithError<Req, E> {
+    _marker: PhantomData<(Req, E)>,
 }
 
+impl<Req, E> Request for WithError<Req, E>
+    where Req: Request,
+          E: DeserializeOwned + Serialize,
+{
+    type Params = Req::Params;
+    type Result = ResultOrError<Req::Result, E>;
+    const METHOD: &'static str = Req::METHOD;
+}
+
+/// The result of `initialize`.
+pub type InitializeOptions = ResultOrError<InitializeResult, InitializeError>;
+
 
 /// Trait encapsulating a client to the language server protocol
 pub trait LspClient {
-    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=Error>>;
+    /// The error returned by requests and notifications.
+    type Error: From<IoError> + 'static;
 
-    // lsdef!(@notify $/cancelRequest, "$/cancelRequest");
-    // lsdef!(@notify initialized, "initialized");
+    fn initialize(&mut self, params: InitializeParams) -> Box<Future<Item=Result<InitializeResult, InitializeError>, Error=Self::Error>>;
+
+    lsdef!(@notify cancel_request, "$/cancelRequest");
+    lsdef!(@notify initialized, "initialized");
     lsdef!(@notify exit, "exit");
     // lsdef!(@notify window/showMessage, "window/showMessage");
     // lsdef!(@notify window/logMessage, "window/logMessage");
@@ -55,31 +87,65 @@ pub trait LspClient {
     // lsdef!(@notify textDocument/publishDiagnostics, "textDocument/publishDiagnostics");
     lsdef!(@notify did_change_configuration, "workspace/didChangeConfiguration");
     lsdef!(@notify did_change_watched_files, "workspace/didChangeWatchedFiles");
+    lsdef!(@notify did_change_workspace_folders, "workspace/didChangeWorkspaceFolders");
 
     // lsdef!(@req initialize, "initialize");
     lsdef!(@req shutdown, "shutdown");
-    // lsdef!(@req window/showMessageRequest, "window/showMessageRequest");
-    // lsdef!(@req client/registerCapability, "client/registerCapability");
-    // lsdef!(@req client/unregisterCapability, "client/unregisterCapability");
+    // window/showMessageRequest is sent by the server, see `handlers::ShowMessageRequestHandler`
+    // client/registerCapability and client/unregisterCapability are sent by the server, see `handlers::RegistrationHandler`
     lsdef!(@req workspace_symbols, "workspace/symbol");
-    // lsdef!(@req workspace/executeCommand, "workspace/executeCommand");
-    // lsdef!(@req textDocument/willSaveWaitUntil, "textDocument/willSaveWaitUntil");
+    lsdef!(@req resolve_workspace_symbol, "workspaceSymbol/resolve");
+    lsdef!(@req execute_command, "workspace/executeCommand");
+    lsdef!(@req will_save_wait_until, "textDocument/willSaveWaitUntil");
     lsdef!(@req completion, "textDocument/completion");
     lsdef!(@req resolve_completion_item, "completionItem/resolve");
     lsdef!(@req hover, "textDocument/hover");
     lsdef!(@req signature_help, "textDocument/signatureHelp");
     lsdef!(@req goto_definition, "textDocument/definition");
+    lsdef!(@req goto_type_definition, "textDocument/typeDefinition");
+    lsdef!(@req goto_implementation, "textDocument/implementation");
+    lsdef!(@req goto_declaration, "textDocument/declaration");
     lsdef!(@req references, "textDocument/references");
     lsdef!(@req document_highlight, "textDocument/documentHighlight");
     lsdef!(@req document_symbols, "textDocument/documentSymbol");
     lsdef!(@req code_action, "textDocument/codeAction");
+    lsdef!(@req resolve_code_action, "codeAction/resolve");
     lsdef!(@req code_lens, "textDocument/codeLens");
     lsdef!(@req code_lens_resolve, "codeLens/resolve");
     lsdef!(@req document_link, "textDocument/documentLink");
     lsdef!(@req document_link_resolve, "documentLink/resolve");
+    lsdef!(@req folding_range, "textDocument/foldingRange");
+    lsdef!(@req selection_range, "textDocument/selectionRange");
+    lsdef!(@req document_color, "textDocument/documentColor");
+    lsdef!(@req color_presentation, "textDocument/colorPresentation");
+    lsdef!(@req prepare_call_hierarchy, "textDocument/prepareCallHierarchy");
+    lsdef!(@req incoming_calls, "callHierarchy/incomingCalls");
+    lsdef!(@req outgoing_calls, "callHierarchy/outgoingCalls");
+    lsdef!(@req semantic_tokens_full, "textDocument/semanticTokens/full");
+    lsdef!(@req semantic_tokens_range, "textDocument/semanticTokens/range");
+    // The report is either "full", or "unchanged" since the previous resultId
+    lsdef!(@req document_diagnostics, "textDocument/diagnostic");
+    lsdef!(@req inlay_hint, "textDocument/inlayHint");
+    lsdef!(@req inlay_hint_resolve, "inlayHint/resolve");
     // lsdef!(@req textDocument/applyEdit, "textDocument/applyEdit");
     lsdef!(@req range_formatting, "textDocument/rangeFormatting");
     lsdef!(@req on_type_formatting, "textDocument/onTypeFormatting");
     lsdef!(@req formatting, "textDocument/formatting");
     lsdef!(@req rename, "textDocument/rename");
+    lsdef!(@req prepare_rename, "textDocument/prepareRename");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_initialize_options() {
+        let result: InitializeOptions = serde_json::from_value(json!({"capabilities": {"hoverProvider": true}})).unwrap();
+        assert_eq!(result.into_result().unwrap().capabilities.hover_provider, Some(true));
+
+        let error: InitializeOptions = serde_json::from_value(json!({"retry": true})).unwrap();
+        assert_eq!(error.into_result().unwrap_err(), InitializeError { retry: true });
+    }
 }
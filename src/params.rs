@@ -0,0 +1,390 @@
+//! Helpers for building the parameters of common requests.
+
+use ls_types::*;
+use serde_json::{self, Value};
+use url::Url;
+
+use std::path::Path;
+use std::process;
+
+use super::LspError;
+use uri::path_to_uri;
+
+/// The client capabilities announced by
+/// `InitializeParamsBuilder::full_capabilities`.
+const FULL_CAPABILITIES: &'static str = r#"{
+    "workspace": {
+        "applyEdit": true,
+        "workspaceEdit": {"documentChanges": true},
+        "didChangeConfiguration": {"dynamicRegistration": false},
+        "didChangeWatchedFiles": {"dynamicRegistration": false},
+        "symbol": {"dynamicRegistration": false},
+        "executeCommand": {"dynamicRegistration": false},
+        "workspaceFolders": true,
+        "configuration": true
+    },
+    "textDocument": {
+        "synchronization": {"willSave": true, "willSaveWaitUntil": true, "didSave": true},
+        "completion": {
+            "completionItem": {
+                "snippetSupport": true,
+                "commitCharactersSupport": true,
+                "documentationFormat": ["markdown", "plaintext"],
+                "deprecatedSupport": true,
+                "resolveSupport": {"properties": ["documentation", "detail", "additionalTextEdits"]}
+            },
+            "contextSupport": true
+        },
+        "hover": {"contentFormat": ["markdown", "plaintext"]},
+        "signatureHelp": {
+            "signatureInformation": {"documentationFormat": ["markdown", "plaintext"]}
+        },
+        "references": {},
+        "documentHighlight": {},
+        "documentSymbol": {"hierarchicalDocumentSymbolSupport": true},
+        "formatting": {},
+        "rangeFormatting": {},
+        "onTypeFormatting": {},
+        "definition": {"linkSupport": true},
+        "codeAction": {
+            "codeActionLiteralSupport": {
+                "codeActionKind": {
+                    "valueSet": ["", "quickfix", "refactor", "refactor.extract", "refactor.inline",
+                                 "refactor.rewrite", "source", "source.organizeImports"]
+                }
+            },
+            "resolveSupport": {"properties": ["edit"]}
+        },
+        "codeLens": {},
+        "documentLink": {},
+        "rename": {"prepareSupport": true},
+        "publishDiagnostics": {"relatedInformation": true}
+    }
+}"#;
+
+/// Builds the parameters for an `initialize` call.
+///
+/// By default the process id is set to that of the current process, and the
+/// (empty) workspace and text document capabilities are announced.
+#[derive(Clone, Debug)]
+pub struct InitializeParamsBuilder {
+    params: InitializeParams,
+}
+
+impl InitializeParamsBuilder {
+    pub fn new() -> Self {
+        InitializeParamsBuilder {
+            params: InitializeParams {
+                process_id: Some(u64::from(process::id())),
+                root_uri: None,
+                root_path: None,
+                initialization_options: None,
+                capabilities: ClientCapabilities {
+                    workspace: Some(Default::default()),
+                    text_document: Some(Default::default()),
+                    experimental: None,
+                },
+                trace: None,
+            },
+        }
+    }
+
+    /// Set the root of the workspace.
+    pub fn root_uri(mut self, uri: Url) -> Self {
+        self.params.root_uri = Some(uri);
+        self
+    }
+
+    /// Set the process id the server should watch, or `None` to not have it
+    /// watch any process.
+    pub fn process_id(mut self, pid: Option<u64>) -> Self {
+        self.params.process_id = pid;
+        self
+    }
+
+    /// Set the server-specific initialization options.
+    pub fn initialization_options(mut self, options: Value) -> Self {
+        self.params.initialization_options = Some(options);
+        self
+    }
+
+    /// Whether to announce any workspace capabilities.
+    pub fn workspace(mut self, enabled: bool) -> Self {
+        self.params.capabilities.workspace = if enabled { Some(Default::default()) } else { None };
+        self
+    }
+
+    /// Announce the given workspace capabilities.
+    pub fn workspace_capabilities(mut self, caps: WorkspaceClientCapabilities) -> Self {
+        self.params.capabilities.workspace = Some(caps);
+        self
+    }
+
+    /// Whether to announce any text document capabilities.
+    pub fn text_document(mut self, enabled: bool) -> Self {
+        self.params.capabilities.text_document = if enabled { Some(Default::default()) } else { None };
+        self
+    }
+
+    /// Announce the given text document capabilities.
+    pub fn text_document_capabilities(mut self, caps: TextDocumentClientCapabilities) -> Self {
+        self.params.capabilities.text_document = Some(caps);
+        self
+    }
+
+    /// Announce support for the commonly wanted features which servers
+    /// otherwise leave out, such as snippet completions, hierarchical
+    /// document symbols, markdown documentation and lazily resolved
+    /// completion items and code actions.
+    ///
+    /// This replaces any workspace and text document capabilities set so far.
+    pub fn full_capabilities(mut self) -> Self {
+        let capabilities: ClientCapabilities = serde_json::from_str(FULL_CAPABILITIES)
+            .expect("malformed full capabilities");
+        self.params.capabilities.workspace = capabilities.workspace;
+        self.params.capabilities.text_document = capabilities.text_document;
+        self
+    }
+
+    /// Set the experimental capabilities.
+    pub fn experimental(mut self, experimental: Value) -> Self {
+        self.params.capabilities.experimental = Some(experimental);
+        self
+    }
+
+    /// Set the initial trace setting.
+    pub fn trace(mut self, trace: TraceOption) -> Self {
+        self.params.trace = Some(trace);
+        self
+    }
+
+    pub fn build(self) -> InitializeParams {
+        self.params
+    }
+}
+
+impl Default for InitializeParamsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The name and version of the client, sent as `clientInfo` with
+/// `initialize` so that servers can identify it in their logs, see
+/// `Client::set_client_info`.
+///
+/// `InitializeParams` does not model the field, so it can't be set through
+/// `InitializeParamsBuilder`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ClientInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl ClientInfo {
+    pub fn new<S: Into<String>>(name: S, version: Option<S>) -> Self {
+        ClientInfo {
+            name: name.into(),
+            version: version.map(Into::into),
+        }
+    }
+}
+
+/// Builds `textDocument/didChange` parameters for an open document, keeping
+/// track of its version.
+#[derive(Clone, Debug)]
+pub struct DidChangeBuilder {
+    document: VersionedTextDocumentIdentifier,
+}
+
+impl DidChangeBuilder {
+    /// Start from the version the document was opened with.
+    pub fn new(uri: Url, version: u64) -> Self {
+        DidChangeBuilder { document: VersionedTextDocumentIdentifier::new(uri, version) }
+    }
+
+    /// The version of the last change built.
+    pub fn document(&self) -> &VersionedTextDocumentIdentifier {
+        &self.document
+    }
+
+    /// Replace the whole text of the document, for servers using full sync.
+    pub fn replace_all<S: Into<String>>(&mut self, text: S) -> DidChangeTextDocumentParams {
+        self.change(TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: text.into(),
+        })
+    }
+
+    /// Replace the text within `range`, for servers using incremental sync.
+    pub fn edit<S: Into<String>>(&mut self, range: Range, text: S) -> DidChangeTextDocumentParams {
+        self.change(TextDocumentContentChangeEvent {
+            range: Some(range),
+            range_length: None,
+            text: text.into(),
+        })
+    }
+
+    fn change(&mut self, change: TextDocumentContentChangeEvent) -> DidChangeTextDocumentParams {
+        self.document.version += 1;
+        DidChangeTextDocumentParams {
+            text_document: self.document.clone(),
+            content_changes: vec![change],
+        }
+    }
+}
+
+/// Build `workspace/didChangeWatchedFiles` parameters reporting the same
+/// kind of change for each of `paths`.
+///
+/// Fails if any of the paths is not absolute.
+pub fn watched_files_changed<P: AsRef<Path>>(paths: &[P], typ: FileChangeType) -> Result<DidChangeWatchedFilesParams, LspError> {
+    let changes = paths.iter()
+        .map(|path| path_to_uri(path.as_ref()).map(|uri| FileEvent::new(uri, typ)))
+        .collect::<Result<_, _>>()?;
+    Ok(DidChangeWatchedFilesParams { changes })
+}
+
+/// Build `textDocument/didSave` parameters, including the text of the
+/// document only if the server asked for it with `save.includeText`.
+///
+/// The text is left out if the capabilities are not yet known.
+pub fn did_save(capabilities: Option<&ServerCapabilities>, document: TextDocumentIdentifier, text: &str) -> DidSaveTextDocumentParams {
+    let include_text = capabilities
+        .and_then(|capabilities| serde_json::to_value(capabilities).ok())
+        .and_then(|capabilities| capabilities.pointer("/textDocumentSync/save/includeText").and_then(Value::as_bool))
+        .unwrap_or(false);
+    DidSaveTextDocumentParams {
+        text_document: document,
+        text: if include_text { Some(text.to_string()) } else { None },
+    }
+}
+
+/// A validated constructor for `FormattingOptions`.
+pub trait FormattingOptionsExt: Sized {
+    /// Fails if `tab_size` is zero, which servers reject.
+    fn checked(tab_size: u32, insert_spaces: bool) -> Result<Self, LspError>;
+}
+
+impl FormattingOptionsExt for FormattingOptions {
+    fn checked(tab_size: u32, insert_spaces: bool) -> Result<Self, LspError> {
+        let options = FormattingOptions {
+            tab_size: tab_size.into(),
+            insert_spaces,
+            ..Default::default()
+        };
+        check_formatting_options(&options)?;
+        Ok(options)
+    }
+}
+
+pub(crate) fn check_formatting_options(options: &FormattingOptions) -> Result<(), LspError> {
+    if options.tab_size == 0 {
+        return Err(LspError::InvalidParams("tab size must be greater than zero".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let root = Url::parse("file:///tmp/project").unwrap();
+        let params = InitializeParamsBuilder::new()
+            .root_uri(root.clone())
+            .text_document(false)
+            .build();
+        assert_eq!(params.root_uri, Some(root));
+        assert!(params.capabilities.workspace.is_some());
+        assert!(params.capabilities.text_document.is_none());
+
+        let value = serde_json::to_value(&params).unwrap();
+        assert_eq!(value["rootUri"], json!("file:///tmp/project"));
+    }
+
+    #[test]
+    fn test_full_capabilities() {
+        let params = InitializeParamsBuilder::new()
+            .text_document(false)
+            .full_capabilities()
+            .build();
+        let capabilities = serde_json::to_value(&params.capabilities).unwrap();
+        assert_eq!(capabilities.pointer("/textDocument/completion/completionItem/snippetSupport"), Some(&json!(true)));
+        assert_eq!(capabilities.pointer("/textDocument/documentSymbol/hierarchicalDocumentSymbolSupport"), Some(&json!(true)));
+        assert_eq!(capabilities.pointer("/textDocument/hover/contentFormat/0"), Some(&json!("markdown")));
+        assert_eq!(capabilities.pointer("/workspace/applyEdit"), Some(&json!(true)));
+    }
+
+    #[test]
+    fn test_formatting_options() {
+        let options = FormattingOptions::checked(4, true).unwrap();
+        assert_eq!(options.tab_size, 4);
+        assert!(options.insert_spaces);
+
+        match FormattingOptions::checked(0, true) {
+            Err(LspError::InvalidParams(_)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_watched_files_changed() {
+        let params = watched_files_changed(&["/tmp/main.rs", "/tmp/my project/lib.rs"], FileChangeType::Changed).unwrap();
+        let uris: Vec<_> = params.changes.iter().map(|change| change.uri.as_str()).collect();
+        assert_eq!(uris, vec!["file:///tmp/main.rs", "file:///tmp/my%20project/lib.rs"]);
+        assert!(params.changes.iter().all(|change| change.typ == FileChangeType::Changed));
+
+        assert!(watched_files_changed(&["src/main.rs"], FileChangeType::Deleted).is_err());
+    }
+
+    #[test]
+    fn test_did_save_include_text() {
+        let capabilities: ServerCapabilities = serde_json::from_value(json!({
+            "textDocumentSync": {"save": {"includeText": true}},
+        })).unwrap();
+        let document = TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap());
+        let params = did_save(Some(&capabilities), document, "fn main() {}");
+        assert_eq!(params.text, Some("fn main() {}".to_string()));
+    }
+
+    #[test]
+    fn test_did_save_without_text() {
+        let document = TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap());
+        for capabilities in vec![json!({"textDocumentSync": {"save": {}}}), json!({"textDocumentSync": 1})] {
+            let capabilities: ServerCapabilities = serde_json::from_value(capabilities).unwrap();
+            let params = did_save(Some(&capabilities), document.clone(), "fn main() {}");
+            assert_eq!(params.text, None);
+        }
+        assert_eq!(did_save(None, document, "fn main() {}").text, None);
+    }
+
+    #[test]
+    fn test_did_change_full() {
+        let uri = Url::parse("file:///tmp/main.rs").unwrap();
+        let mut changes = DidChangeBuilder::new(uri.clone(), 1);
+        let params = changes.replace_all("fn main() {}");
+        assert_eq!(params.text_document, VersionedTextDocumentIdentifier::new(uri, 2));
+        assert_eq!(params.content_changes, vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: "fn main() {}".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_did_change_incremental() {
+        let uri = Url::parse("file:///tmp/main.rs").unwrap();
+        let mut changes = DidChangeBuilder::new(uri, 1);
+        let range = Range::new(Position::new(0, 3), Position::new(0, 7));
+        changes.edit(range, "start");
+        let params = changes.edit(range, "run");
+        assert_eq!(params.text_document.version, 3);
+        assert_eq!(params.content_changes[0].range, Some(range));
+        assert_eq!(params.content_changes[0].text, "run");
+    }
+}
@@ -0,0 +1,172 @@
+//! Handling of the encoding in which `Position` columns are counted.
+
+use ls_types::Position;
+
+use std::cmp;
+
+/// The unit in which the `character` of a `Position` is counted, as
+/// negotiated through `positionEncoding`.
+///
+/// Servers which don't negotiate an encoding use UTF-16.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum PositionEncoding {
+    /// Bytes of UTF-8.
+    #[serde(rename = "utf-8")]
+    Utf8,
+    /// UTF-16 code units.
+    #[serde(rename = "utf-16")]
+    Utf16,
+    /// Unicode code points.
+    #[serde(rename = "utf-32")]
+    Utf32,
+}
+
+impl Default for PositionEncoding {
+    fn default() -> Self {
+        PositionEncoding::Utf16
+    }
+}
+
+/// Converts between byte offsets into a document's (UTF-8) text, and LSP
+/// `Position`s counted in a given encoding.
+///
+/// Lines may be terminated by `\n`, `\r\n` or `\r`.
+#[derive(Clone, Debug)]
+pub struct PositionMapper<'a> {
+    text: &'a str,
+    encoding: PositionEncoding,
+    /// The byte offset at which each line starts.
+    line_starts: Vec<usize>,
+}
+
+impl<'a> PositionMapper<'a> {
+    pub fn new(text: &'a str, encoding: PositionEncoding) -> Self {
+        let bytes = text.as_bytes();
+        let mut line_starts = vec![0];
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => {
+                    line_starts.push(i + 2);
+                    i += 1;
+                },
+                b'\r' | b'\n' => line_starts.push(i + 1),
+                _ => (),
+            }
+            i += 1;
+        }
+        PositionMapper { text, encoding, line_starts }
+    }
+
+    /// The position of the byte `offset`, or `None` if it is past the end of
+    /// the text or not on a character boundary.
+    pub fn position(&self, offset: usize) -> Option<Position> {
+        if offset > self.text.len() || !self.text.is_char_boundary(offset) {
+            return None;
+        }
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next) => next - 1,
+        };
+        let content = self.line(line);
+        let start = self.line_starts[line];
+        // An offset within the line terminator maps to the end of the line
+        let end = cmp::min(offset - start, content.len());
+        Some(Position::new(line as _, self.units(&content[..end]) as _))
+    }
+
+    /// The byte offset of `position`, or `None` if its line is past the end
+    /// of the text.
+    ///
+    /// As per the spec, a character past the end of the line maps to the end
+    /// of the line. A character in the middle of a multi-unit character maps
+    /// to the start of that character.
+    pub fn offset(&self, position: Position) -> Option<usize> {
+        let line = position.line as usize;
+        if line >= self.line_starts.len() {
+            return None;
+        }
+        let content = self.line(line);
+        let target = position.character as usize;
+        let mut units = 0;
+        for (idx, c) in content.char_indices() {
+            units += self.char_units(c);
+            if units > target {
+                return Some(self.line_starts[line] + idx);
+            }
+        }
+        Some(self.line_starts[line] + content.len())
+    }
+
+    /// The text of `line`, without its terminator.
+    fn line(&self, line: usize) -> &'a str {
+        let start = self.line_starts[line];
+        let end = self.line_starts.get(line + 1).cloned().unwrap_or(self.text.len());
+        self.text[start..end].trim_end_matches(|c| c == '\n' || c == '\r')
+    }
+
+    fn units(&self, s: &str) -> usize {
+        match self.encoding {
+            PositionEncoding::Utf8 => s.len(),
+            _ => s.chars().map(|c| self.char_units(c)).sum(),
+        }
+    }
+
+    fn char_units(&self, c: char) -> usize {
+        match self.encoding {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &'static str = "let 🦀 = 1;\r\nx\n";
+
+    #[test]
+    fn test_utf16() {
+        let mapper = PositionMapper::new(TEXT, PositionEncoding::Utf16);
+        // The crab is a surrogate pair
+        assert_eq!(mapper.position(9), Some(Position::new(0, 7)));
+        assert_eq!(mapper.offset(Position::new(0, 7)), Some(9));
+        // Within the pair
+        assert_eq!(mapper.offset(Position::new(0, 5)), Some(4));
+        assert_eq!(mapper.position(6), None);
+
+        assert_eq!(mapper.position(15), Some(Position::new(1, 0)));
+        assert_eq!(mapper.offset(Position::new(1, 0)), Some(15));
+        // Past the end of a line, or within its terminator
+        assert_eq!(mapper.offset(Position::new(0, 100)), Some(13));
+        assert_eq!(mapper.position(14), Some(Position::new(0, 11)));
+        // The empty last line
+        assert_eq!(mapper.position(TEXT.len()), Some(Position::new(2, 0)));
+        assert_eq!(mapper.offset(Position::new(3, 0)), None);
+    }
+
+    #[test]
+    fn test_utf8() {
+        let mapper = PositionMapper::new(TEXT, PositionEncoding::Utf8);
+        assert_eq!(mapper.position(9), Some(Position::new(0, 9)));
+        assert_eq!(mapper.offset(Position::new(0, 9)), Some(9));
+        assert_eq!(mapper.offset(Position::new(0, 6)), Some(4));
+        assert_eq!(mapper.position(16), Some(Position::new(1, 1)));
+    }
+
+    #[test]
+    fn test_utf32() {
+        let mapper = PositionMapper::new(TEXT, PositionEncoding::Utf32);
+        assert_eq!(mapper.position(9), Some(Position::new(0, 6)));
+        assert_eq!(mapper.offset(Position::new(0, 6)), Some(9));
+    }
+
+    #[test]
+    fn test_line_terminators() {
+        let mapper = PositionMapper::new("a\rb\r\nc\nd", PositionEncoding::Utf16);
+        let positions: Vec<_> = vec![2, 5, 7].into_iter().map(|offset| mapper.position(offset).unwrap()).collect();
+        assert_eq!(positions, vec![Position::new(1, 0), Position::new(2, 0), Position::new(3, 0)]);
+    }
+}
@@ -0,0 +1,204 @@
+//! Traits for implementing a language server, with the same typed messages
+//! as the clients.
+
+#![allow(unused_variables)]
+
+use futures::{future, Future};
+use jsonrpc::{server, ServerCtl};
+use jsonrpc::message::RpcError;
+use ls_types::*;
+use ls_types::notification::Notification;
+use ls_types::request::Request;
+use serde::Serialize;
+use serde_json::{self, Value};
+
+use client::handlers::parse_params;
+
+/// The error for requests the server does not handle.
+fn method_not_found(method: &str) -> RpcError {
+    RpcError {
+        code: -32601,
+        message: format!("Method not found: {}", method),
+        data: None,
+    }
+}
+
+/// Serialize the result of a handler into the response.
+fn respond<T>(result: Result<Box<Future<Item=T, Error=RpcError>>, RpcError>) -> Box<Future<Item=Value, Error=RpcError>>
+    where T: Serialize + 'static
+{
+    match result {
+        Ok(response) => Box::new(response.and_then(|result| serde_json::to_value(result).map_err(|e| RpcError {
+            code: -32603,
+            message: format!("Internal error: {}", e),
+            data: None,
+        }))),
+        Err(e) => Box::new(future::err(e)),
+    }
+}
+
+/// Adapts an `LspServer` to the jsonrpc `Server` trait, so that it can be
+/// passed to `Endpoint::new`.
+pub struct ServerAdapter<S> {
+    server: S,
+}
+
+impl<S: LspServer> ServerAdapter<S> {
+    pub fn new(server: S) -> Self {
+        ServerAdapter { server }
+    }
+}
+
+macro_rules! lsserve {
+    (requests { $($req_fn:ident => $req:tt,)* } notifications { $($not_fn:ident => $not:tt,)* }) => {
+        /// Trait encapsulating a language server.
+        ///
+        /// Only `initialize` must be implemented: other requests are answered
+        /// with a "method not found" error, and notifications are ignored.
+        pub trait LspServer {
+            fn initialize(&self, params: InitializeParams) -> Box<Future<Item=InitializeResult, Error=RpcError>>;
+
+            $(
+                fn $req_fn(&self, params: <request!($req) as Request>::Params) -> Box<Future<Item=<request!($req) as Request>::Result, Error=RpcError>> {
+                    Box::new(future::err(method_not_found($req)))
+                }
+            )*
+
+            $(
+                fn $not_fn(&self, params: <lsp_notification!($not) as Notification>::Params) {}
+            )*
+        }
+
+        impl<S: LspServer> server::Server for ServerAdapter<S> {
+            type Success = Value;
+            type RpcCallResult = Box<Future<Item=Value, Error=RpcError>>;
+            type NotificationResult = Result<(), ()>;
+
+            fn rpc(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::RpcCallResult> {
+                match method {
+                    "initialize" => Some(respond(parse_params(params).map(|params| self.server.initialize(params)))),
+                    $(
+                        $req => Some(respond(parse_params(params).map(|params| self.server.$req_fn(params)))),
+                    )*
+                    _ => None,
+                }
+            }
+
+            fn notification(&self, _ctl: &ServerCtl, method: &str, params: &Option<Value>) -> Option<Self::NotificationResult> {
+                match method {
+                    $(
+                        $not => Some(parse_params(params).map(|params| self.server.$not_fn(params)).map_err(|e| {
+                            warn!("ignoring {}: {}", method, e.message);
+                        })),
+                    )*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+lsserve! {
+    requests {
+        shutdown => "shutdown",
+        workspace_symbols => "workspace/symbol",
+        resolve_workspace_symbol => "workspaceSymbol/resolve",
+        execute_command => "workspace/executeCommand",
+        will_save_wait_until => "textDocument/willSaveWaitUntil",
+        completion => "textDocument/completion",
+        resolve_completion_item => "completionItem/resolve",
+        hover => "textDocument/hover",
+        signature_help => "textDocument/signatureHelp",
+        goto_definition => "textDocument/definition",
+        goto_type_definition => "textDocument/typeDefinition",
+        goto_implementation => "textDocument/implementation",
+        goto_declaration => "textDocument/declaration",
+        references => "textDocument/references",
+        document_highlight => "textDocument/documentHighlight",
+        document_symbols => "textDocument/documentSymbol",
+        code_action => "textDocument/codeAction",
+        resolve_code_action => "codeAction/resolve",
+        code_lens => "textDocument/codeLens",
+        code_lens_resolve => "codeLens/resolve",
+        document_link => "textDocument/documentLink",
+        document_link_resolve => "documentLink/resolve",
+        folding_range => "textDocument/foldingRange",
+        selection_range => "textDocument/selectionRange",
+        document_color => "textDocument/documentColor",
+        color_presentation => "textDocument/colorPresentation",
+        prepare_call_hierarchy => "textDocument/prepareCallHierarchy",
+        incoming_calls => "callHierarchy/incomingCalls",
+        outgoing_calls => "callHierarchy/outgoingCalls",
+        semantic_tokens_full => "textDocument/semanticTokens/full",
+        semantic_tokens_range => "textDocument/semanticTokens/range",
+        document_diagnostics => "textDocument/diagnostic",
+        inlay_hint => "textDocument/inlayHint",
+        inlay_hint_resolve => "inlayHint/resolve",
+        range_formatting => "textDocument/rangeFormatting",
+        on_type_formatting => "textDocument/onTypeFormatting",
+        formatting => "textDocument/formatting",
+        rename => "textDocument/rename",
+        prepare_rename => "textDocument/prepareRename",
+    }
+    notifications {
+        cancel_request => "$/cancelRequest",
+        initialized => "initialized",
+        exit => "exit",
+        did_open_text_document => "textDocument/didOpen",
+        did_change_text_document => "textDocument/didChange",
+        did_save_text_document => "textDocument/didSave",
+        did_close_text_document => "textDocument/didClose",
+        did_change_configuration => "workspace/didChangeConfiguration",
+        did_change_watched_files => "workspace/didChangeWatchedFiles",
+        did_change_workspace_folders => "workspace/didChangeWorkspaceFolders",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client::Client;
+    use jsonrpc::Endpoint;
+    use lsp::LspClient;
+    use test_util;
+    use tokio_core::reactor::Core;
+    use LspError;
+
+    /// Answers hovers with the position hovered over.
+    struct EchoHover;
+
+    impl LspServer for EchoHover {
+        fn initialize(&self, _params: InitializeParams) -> Box<Future<Item=InitializeResult, Error=RpcError>> {
+            Box::new(future::ok(serde_json::from_value(json!({"capabilities": {"hoverProvider": true}})).unwrap()))
+        }
+
+        fn hover(&self, params: TextDocumentPositionParams) -> Box<Future<Item=Option<Hover>, Error=RpcError>> {
+            let position = params.position;
+            Box::new(future::ok(Some(Hover {
+                contents: HoverContents::Scalar(MarkedString::String(format!("{}:{}", position.line, position.character))),
+                range: None,
+            })))
+        }
+    }
+
+    #[test]
+    fn test_echo_hover() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let (client_end, server_end) = test_util::connected(&handle);
+        let (_server, _driver) = Endpoint::new(server_end, ServerAdapter::new(EchoHover)).start(&handle);
+        let mut client = Client::new(client_end, &handle);
+
+        core.run(client.initialize(test_util::init_params())).unwrap().unwrap();
+        let hover = core.run(client.hover(TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap()),
+            position: Position::new(3, 7),
+        })).unwrap().unwrap();
+        assert_eq!(hover.contents, HoverContents::Scalar(MarkedString::String("3:7".to_string())));
+
+        match core.run(client.shutdown(())) {
+            Err(LspError::ServerError(e)) => assert_eq!(e.code, -32601),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
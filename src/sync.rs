@@ -0,0 +1,229 @@
+//! A blocking facade over `Client`, for simple tools and tests which would
+//! rather not deal with futures.
+
+use futures::{Sink, Stream};
+use jsonrpc::{server, Message, Parsed};
+use ls_types::*;
+use ls_types::notification::Notification;
+use ls_types::request::Request;
+use serde;
+use serde_json::Value;
+use tokio_core::reactor::{Core, Handle};
+
+use std::io::{Error as IoError, Result as IoResult};
+use std::process::Command;
+use std::time::Duration;
+
+use client::Client;
+use lsp::LspClient;
+use super::LspError;
+
+/// A client which owns its own reactor, and runs it until each call has
+/// completed.
+pub struct BlockingClient {
+    client: Client,
+    core: Core,
+}
+
+macro_rules! blocking {
+    (@req $fn_name:ident, $name:tt) => {
+        pub fn $fn_name(&mut self, params: <request!($name) as Request>::Params) -> Result<<request!($name) as Request>::Result, LspError> {
+            self.core.run(self.client.$fn_name(params))
+        }
+    };
+    (@notify $fn_name:ident, $name:tt) => {
+        pub fn $fn_name(&mut self, params: <lsp_notification!($name) as Notification>::Params) -> Result<(), LspError> {
+            self.notify::<lsp_notification!($name)>(params)
+        }
+    };
+}
+
+impl BlockingClient {
+    /// Create a new `BlockingClient` with the given connection.
+    pub fn new<C>(connection: C) -> IoResult<Self>
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+    {
+        let core = Core::new()?;
+        let client = Client::new(connection, &core.handle());
+        Ok(BlockingClient { client, core })
+    }
+
+    /// Create a new `BlockingClient` with a provided handler to handle
+    /// incoming notifications.
+    ///
+    /// Notifications are only handled while a call is in progress.
+    pub fn with_notification_handler<C, NH>(connection: C, notification_handler: NH) -> IoResult<Self>
+        where
+            C: Stream<Item = Parsed, Error = IoError>,
+            C: Sink<SinkItem = Message, SinkError = IoError>,
+            C: Send + 'static,
+            NH: server::Server + 'static
+    {
+        let core = Core::new()?;
+        let client = Client::with_notification_handler(connection, notification_handler, &core.handle());
+        Ok(BlockingClient { client, core })
+    }
+
+    /// Launch the language server `cmd`, see `Client::spawn`.
+    pub fn spawn(cmd: Command) -> IoResult<Self> {
+        let core = Core::new()?;
+        let client = Client::spawn(cmd, &core.handle())?;
+        Ok(BlockingClient { client, core })
+    }
+
+    /// A handle to the reactor driving the client.
+    pub fn handle(&self) -> Handle {
+        self.core.handle()
+    }
+
+    /// The underlying async client.
+    pub fn client(&mut self) -> &mut Client {
+        &mut self.client
+    }
+
+    /// Run the reactor for whatever is ready, without blocking, and take the
+    /// notifications received since the last call.
+    ///
+    /// Always empty unless `Client::buffer_notifications` was called, see
+    /// `client`.
+    pub fn poll_notifications(&mut self) -> Vec<(String, Option<Value>)> {
+        let mut notifications = Vec::new();
+        loop {
+            self.core.turn(Some(Duration::from_millis(0)));
+            let received = self.client.poll_notifications();
+            if received.is_empty() {
+                return notifications;
+            }
+            notifications.extend(received);
+        }
+    }
+
+    /// Make a request, see `Client::call`.
+    pub fn call<Req>(&mut self, params: Req::Params) -> Result<Req::Result, LspError>
+        where Req: Request,
+              Req::Params: serde::Serialize,
+              Req::Result: serde::de::DeserializeOwned + 'static,
+    {
+        self.core.run(self.client.call::<Req>(params))
+    }
+
+    /// Send a notification, and wait until it has been handed over to the
+    /// connection.
+    pub fn notify<Not>(&mut self, params: Not::Params) -> Result<(), LspError>
+        where Not: Notification,
+              Not::Params: serde::Serialize,
+    {
+        self.core.run(self.client.notify_sent::<Not>(params))
+    }
+
+    pub fn initialize(&mut self, params: InitializeParams) -> Result<Result<InitializeResult, InitializeError>, LspError> {
+        self.core.run(self.client.initialize(params))
+    }
+
+    /// Send `shutdown` followed by `exit`, see `Client::shutdown_and_exit`.
+    pub fn shutdown_and_exit(self) -> Result<(), LspError> {
+        let BlockingClient { client, mut core } = self;
+        core.run(client.shutdown_and_exit())
+    }
+
+    blocking!(@notify cancel_request, "$/cancelRequest");
+    blocking!(@notify initialized, "initialized");
+    blocking!(@notify exit, "exit");
+    blocking!(@notify did_open_text_document, "textDocument/didOpen");
+    blocking!(@notify did_change_text_document, "textDocument/didChange");
+    blocking!(@notify did_save_text_document, "textDocument/didSave");
+    blocking!(@notify did_close_text_document, "textDocument/didClose");
+    blocking!(@notify did_change_configuration, "workspace/didChangeConfiguration");
+    blocking!(@notify did_change_watched_files, "workspace/didChangeWatchedFiles");
+    blocking!(@notify did_change_workspace_folders, "workspace/didChangeWorkspaceFolders");
+
+    blocking!(@req shutdown, "shutdown");
+    blocking!(@req workspace_symbols, "workspace/symbol");
+    blocking!(@req resolve_workspace_symbol, "workspaceSymbol/resolve");
+    blocking!(@req execute_command, "workspace/executeCommand");
+    blocking!(@req will_save_wait_until, "textDocument/willSaveWaitUntil");
+    blocking!(@req completion, "textDocument/completion");
+    blocking!(@req resolve_completion_item, "completionItem/resolve");
+    blocking!(@req hover, "textDocument/hover");
+    blocking!(@req signature_help, "textDocument/signatureHelp");
+    blocking!(@req goto_definition, "textDocument/definition");
+    blocking!(@req goto_type_definition, "textDocument/typeDefinition");
+    blocking!(@req goto_implementation, "textDocument/implementation");
+    blocking!(@req goto_declaration, "textDocument/declaration");
+    blocking!(@req references, "textDocument/references");
+    blocking!(@req document_highlight, "textDocument/documentHighlight");
+    blocking!(@req document_symbols, "textDocument/documentSymbol");
+    blocking!(@req code_action, "textDocument/codeAction");
+    blocking!(@req resolve_code_action, "codeAction/resolve");
+    blocking!(@req code_lens, "textDocument/codeLens");
+    blocking!(@req code_lens_resolve, "codeLens/resolve");
+    blocking!(@req document_link, "textDocument/documentLink");
+    blocking!(@req document_link_resolve, "documentLink/resolve");
+    blocking!(@req folding_range, "textDocument/foldingRange");
+    blocking!(@req selection_range, "textDocument/selectionRange");
+    blocking!(@req document_color, "textDocument/documentColor");
+    blocking!(@req color_presentation, "textDocument/colorPresentation");
+    blocking!(@req prepare_call_hierarchy, "textDocument/prepareCallHierarchy");
+    blocking!(@req incoming_calls, "callHierarchy/incomingCalls");
+    blocking!(@req outgoing_calls, "callHierarchy/outgoingCalls");
+    blocking!(@req semantic_tokens_full, "textDocument/semanticTokens/full");
+    blocking!(@req semantic_tokens_range, "textDocument/semanticTokens/range");
+    blocking!(@req document_diagnostics, "textDocument/diagnostic");
+    blocking!(@req inlay_hint, "textDocument/inlayHint");
+    blocking!(@req inlay_hint_resolve, "inlayHint/resolve");
+    blocking!(@req range_formatting, "textDocument/rangeFormatting");
+    blocking!(@req on_type_formatting, "textDocument/onTypeFormatting");
+    blocking!(@req formatting, "textDocument/formatting");
+    blocking!(@req rename, "textDocument/rename");
+    blocking!(@req prepare_rename, "textDocument/prepareRename");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_util;
+
+    #[test]
+    fn test_initialize_and_hover() {
+        let (pipe, remote) = test_util::pipe();
+        let mut client = BlockingClient::new(pipe).unwrap();
+        let received = test_util::serve(remote, &client.handle(), |req| match req.method.as_str() {
+            "initialize" => Some(json!({"capabilities": {"hoverProvider": true}})),
+            _ => Some(json!({"contents": "docs"})),
+        });
+
+        client.initialize(test_util::init_params()).unwrap().unwrap();
+        client.initialized(InitializedParams {}).unwrap();
+        let hover = client.hover(TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier::new("file:///tmp/main.rs".parse().unwrap()),
+            position: Position::new(0, 0),
+        }).unwrap();
+
+        assert!(hover.is_some());
+        assert_eq!(received.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_poll_notifications() {
+        let (pipe, remote) = test_util::pipe();
+        let mut client = BlockingClient::new(pipe).unwrap();
+        client.client().buffer_notifications();
+
+        for message in vec!["building", "indexing"] {
+            remote.to_client.unbounded_send(Ok(Message::notification(
+                "window/logMessage".to_string(),
+                Some(json!({"type": 3, "message": message})),
+            ))).unwrap();
+        }
+        let notifications = client.poll_notifications();
+        let messages: Vec<_> = notifications.iter().map(|&(ref method, ref params)| {
+            assert_eq!(method, "window/logMessage");
+            params.as_ref().unwrap()["message"].clone()
+        }).collect();
+        assert_eq!(messages, vec![json!("building"), json!("indexing")]);
+        assert!(client.poll_notifications().is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+//! In-memory transport used by the client tests in place of a real server.
+
+use futures::{future, Future, Poll, Sink, StartSend, Stream};
+use futures::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use jsonrpc::{Endpoint, Message, Parsed};
+use jsonrpc::message::Request;
+use ls_types::{ClientCapabilities, InitializeParams};
+use serde_json::Value;
+use tokio_core::net::TcpListener;
+use tokio_core::reactor::{Core, Handle, Timeout};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, RefCell};
+use std::io::Error as IoError;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::time::Duration;
+
+use client::Client;
+use server::{LspServer, ServerAdapter};
+use transport;
+use super::{custom_err, LspError};
+
+/// An allocator keeping count of the bytes allocated by each thread, see
+/// `peak_allocated`.
+pub struct Counting;
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = Cell::new(0);
+    static PEAK: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|allocated| {
+            let now = allocated.get() + layout.size();
+            allocated.set(now);
+            let _ = PEAK.try_with(|peak| if now > peak.get() { peak.set(now) });
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Memory may be freed by a different thread than allocated it
+        let _ = ALLOCATED.try_with(|allocated| allocated.set(allocated.get().saturating_sub(layout.size())));
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Run `f`, returning its result along with the most memory it had
+/// allocated (and not yet freed) on this thread at any one time.
+pub fn peak_allocated<F, R>(f: F) -> (R, usize)
+    where F: FnOnce() -> R
+{
+    let start = ALLOCATED.with(Cell::get);
+    PEAK.with(|peak| peak.set(start));
+    let result = f();
+    (result, PEAK.with(Cell::get) - start)
+}
+
+/// The client end of an in-memory connection.
+pub struct Pipe {
+    incoming: UnboundedReceiver<Parsed>,
+    outgoing: UnboundedSender<Message>,
+}
+
+/// The server end of an in-memory connection.
+pub struct Remote {
+    pub to_client: UnboundedSender<Parsed>,
+    pub from_client: UnboundedReceiver<Message>,
+}
+
+/// Create a connected `Pipe`/`Remote` pair.
+pub fn pipe() -> (Pipe, Remote) {
+    let (to_client, incoming) = mpsc::unbounded();
+    let (outgoing, from_client) = mpsc::unbounded();
+    (Pipe { incoming, outgoing }, Remote { to_client, from_client })
+}
+
+impl Stream for Pipe {
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        self.incoming.poll().map_err(|_e| custom_err("pipe closed"))
+    }
+}
+
+impl Sink for Pipe {
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, item: Message) -> StartSend<Message, IoError> {
+        self.outgoing.start_send(item).map_err(|_e| custom_err("pipe closed"))
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        self.outgoing.poll_complete().map_err(|_e| custom_err("pipe closed"))
+    }
+}
+
+/// Create two `Pipe`s connected to each other, for running a real server
+/// endpoint against a client.
+pub fn connected(handle: &Handle) -> (Pipe, Pipe) {
+    let (a, a_remote) = pipe();
+    let (b, b_remote) = pipe();
+    let (a_to, b_to) = (a_remote.to_client, b_remote.to_client);
+    handle.spawn(a_remote.from_client.for_each(move |msg| b_to.unbounded_send(Ok(msg)).map_err(|_e| ())));
+    handle.spawn(b_remote.from_client.for_each(move |msg| a_to.unbounded_send(Ok(msg)).map_err(|_e| ())));
+    (a, b)
+}
+
+/// Serve `server` on a local TCP port, accepting a single connection.
+///
+/// Returns the address to connect to.
+pub fn serve_tcp<S: LspServer + 'static>(server: S, handle: &Handle) -> SocketAddr {
+    let listener = TcpListener::bind(&"127.0.0.1:0".parse().unwrap(), handle).unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server_handle = handle.clone();
+    handle.spawn(transport::accept(listener).and_then(move |connection| {
+        let (client, driver) = Endpoint::new(connection, ServerAdapter::new(server)).start(&server_handle);
+        driver.map(move |()| drop(client))
+    }).map_err(|e| panic!("server failed: {}", e)));
+    addr
+}
+
+/// Spawn a stub server on `handle` which answers every request using `answer`
+/// (no reply is sent for `None`).
+///
+/// Returns a log of every message received from the client.
+pub fn serve<F>(remote: Remote, handle: &Handle, answer: F) -> Rc<RefCell<Vec<Message>>>
+    where F: Fn(&Request) -> Option<Value> + 'static
+{
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let log = received.clone();
+    let to_client = remote.to_client;
+    handle.spawn(remote.from_client.for_each(move |msg| {
+        if let Message::Request(ref req) = msg {
+            if let Some(value) = answer(req) {
+                let _ = to_client.unbounded_send(Ok(req.reply(value)));
+            }
+        }
+        log.borrow_mut().push(msg);
+        Ok(())
+    }));
+    received
+}
+
+/// Run the reactor for a little while, so that spawned work gets done.
+pub fn settle(core: &mut Core) {
+    let timeout = Timeout::new(Duration::from_millis(20), &core.handle()).unwrap();
+    core.run(timeout).unwrap();
+}
+
+/// Wait for the next item to arrive on `rx`.
+pub fn recv<T>(core: &mut Core, rx: UnboundedReceiver<T>) -> (T, UnboundedReceiver<T>) {
+    match core.run(rx.into_future()) {
+        Ok((Some(item), rx)) => (item, rx),
+        _ => panic!("channel closed"),
+    }
+}
+
+/// Make a call against a stub server which answers with `result`.
+///
+/// Returns the decoded result along with the request the server received.
+pub fn roundtrip<F, R>(result: Value, f: F) -> (R, Request)
+    where F: FnOnce(&mut Client) -> Box<Future<Item=R, Error=LspError>> + 'static,
+          R: 'static,
+{
+    let mut core = Core::new().unwrap();
+    let handle = core.handle();
+    let (pipe, remote) = pipe();
+    let received = serve(remote, &handle, move |_req| Some(result.clone()));
+    let mut client = Client::new(pipe, &handle);
+
+    let resp = core.run(future::lazy(move || f(&mut client))).unwrap();
+    let req = match received.borrow()[0] {
+        Message::Request(ref req) => req.clone(),
+        ref m => panic!("unexpected message: {:?}", m),
+    };
+    (resp, req)
+}
+
+/// Minimal parameters for an `initialize` call.
+pub fn init_params() -> InitializeParams {
+    InitializeParams {
+        process_id: None,
+        root_uri: None,
+        root_path: None,
+        initialization_options: None,
+        capabilities: ClientCapabilities {
+            workspace: None,
+            text_document: None,
+            experimental: None,
+        },
+        trace: None,
+    }
+}
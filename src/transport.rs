@@ -0,0 +1,225 @@
+//! Helpers for framing the common LSP transports with `LspCodec`.
+
+use futures::{Async, Future, Poll, Sink, StartSend, Stream};
+use jsonrpc::{Message, Parsed};
+use tokio_core::net::{TcpListener, TcpStream};
+use tokio_core::reactor::Handle;
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_io::codec::{Framed, FramedRead, FramedWrite};
+use tokio_process::{Child, ChildStdin, ChildStdout, CommandExt};
+
+use std::io::{Error as IoError, Result as IoResult};
+use std::net::SocketAddr;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex, Weak};
+
+use super::custom_err;
+use codec::LspCodec;
+
+/// Joins a separate reader and writer (e.g. the stdout and stdin of a
+/// language server process) into a single framed connection.
+pub struct StdioConnection<R, W> {
+    read: FramedRead<R, LspCodec>,
+    write: FramedWrite<W, LspCodec>,
+}
+
+impl<R, W> StdioConnection<R, W>
+    where R: AsyncRead,
+          W: AsyncWrite,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        StdioConnection {
+            read: FramedRead::new(reader, LspCodec::default()),
+            write: FramedWrite::new(writer, LspCodec::default()),
+        }
+    }
+}
+
+impl<R: AsyncRead, W> Stream for StdioConnection<R, W> {
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        self.read.poll()
+    }
+}
+
+impl<R, W: AsyncWrite> Sink for StdioConnection<R, W> {
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, IoError> {
+        self.write.start_send(msg)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        self.write.poll_complete()
+    }
+}
+
+/// A server process launched with `launch`.
+///
+/// The process is killed once every clone has been dropped, unless it has
+/// been taken out of `child`.
+#[derive(Clone, Default)]
+pub(crate) struct Process {
+    pub child: Arc<Mutex<Option<Child>>>,
+    /// Set once the connection has seen the process exit.
+    pub status: Arc<Mutex<Option<ExitStatus>>>,
+}
+
+/// The connection to a process launched with `launch`, which also watches
+/// for the process exiting.
+///
+/// Once the process has closed its stdout, the connection only ends after
+/// the process has exited, so that its exit status is known by then.
+pub(crate) struct ProcessConnection {
+    connection: StdioConnection<ChildStdout, ChildStdin>,
+    /// Weak, so that the connection does not keep the process alive.
+    child: Weak<Mutex<Option<Child>>>,
+    status: Arc<Mutex<Option<ExitStatus>>>,
+}
+
+impl ProcessConnection {
+    /// Ready once the process has exited (recording its status), or is no
+    /// longer watched.
+    fn poll_exit(&mut self) -> Poll<(), IoError> {
+        if self.status.lock().unwrap().is_some() {
+            return Ok(Async::Ready(()));
+        }
+        let child = match self.child.upgrade() {
+            Some(child) => child,
+            None => return Ok(Async::Ready(())),
+        };
+        let mut child = child.lock().unwrap();
+        let status = match *child {
+            Some(ref mut child) => match child.poll()? {
+                Async::Ready(status) => status,
+                Async::NotReady => return Ok(Async::NotReady),
+            },
+            // Taken by `Client::shutdown_and_exit`, which waits for it itself
+            None => return Ok(Async::Ready(())),
+        };
+        *self.status.lock().unwrap() = Some(status);
+        Ok(Async::Ready(()))
+    }
+}
+
+impl Stream for ProcessConnection {
+    type Item = Parsed;
+    type Error = IoError;
+    fn poll(&mut self) -> Poll<Option<Parsed>, IoError> {
+        match self.connection.poll()? {
+            Async::Ready(Some(msg)) => Ok(Async::Ready(Some(msg))),
+            // The process closed its stdout, most likely by exiting
+            Async::Ready(None) => match self.poll_exit()? {
+                Async::Ready(()) => Ok(Async::Ready(None)),
+                Async::NotReady => Ok(Async::NotReady),
+            },
+            Async::NotReady => {
+                self.poll_exit()?;
+                Ok(Async::NotReady)
+            },
+        }
+    }
+}
+
+impl Sink for ProcessConnection {
+    type SinkItem = Message;
+    type SinkError = IoError;
+    fn start_send(&mut self, msg: Message) -> StartSend<Message, IoError> {
+        self.connection.start_send(msg)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), IoError> {
+        self.connection.poll_complete()
+    }
+}
+
+/// Launch `cmd` with piped stdin/stdout, returning the process along with a
+/// connection to it.
+pub(crate) fn launch(mut cmd: Command) -> IoResult<(Process, ProcessConnection)> {
+    cmd.stdin(Stdio::piped()).stdout(Stdio::piped());
+    let mut child = cmd.spawn_async()?;
+    let stdout = child.stdout().take().ok_or(custom_err("Failed to capture stdout of the server"))?;
+    let stdin = child.stdin().take().ok_or(custom_err("Failed to capture stdin of the server"))?;
+    let process = Process {
+        child: Arc::new(Mutex::new(Some(child))),
+        status: Arc::default(),
+    };
+    let connection = ProcessConnection {
+        connection: StdioConnection::new(stdout, stdin),
+        child: Arc::downgrade(&process.child),
+        status: process.status.clone(),
+    };
+    Ok((process, connection))
+}
+
+/// A TCP connection framed with `LspCodec`.
+pub type TcpConnection = Framed<TcpStream, LspCodec>;
+
+/// Connect to a language server listening on `addr`.
+pub fn connect(addr: &SocketAddr, handle: &Handle) -> Box<Future<Item=TcpConnection, Error=IoError>> {
+    Box::new(TcpStream::connect(addr, handle).map(|stream| stream.framed(LspCodec::default())))
+}
+
+/// Accept connections on `listener`, e.g. to serve an `LspServer` over TCP
+/// or to accept a test client.
+pub fn incoming(listener: TcpListener) -> Box<Stream<Item=(TcpConnection, SocketAddr), Error=IoError>> {
+    Box::new(listener.incoming().map(|(stream, addr)| (stream.framed(LspCodec::default()), addr)))
+}
+
+/// Accept a single connection on `listener`.
+pub fn accept(listener: TcpListener) -> Box<Future<Item=TcpConnection, Error=IoError>> {
+    Box::new(incoming(listener).into_future()
+        .map_err(|(e, _incoming)| e)
+        .and_then(|(connection, _incoming)| {
+            connection.map(|(connection, _addr)| connection)
+                .ok_or_else(|| custom_err("Listener closed"))
+        }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+    use jsonrpc::message::RpcError;
+    use ls_types::*;
+    use serde_json;
+    use tokio_core::reactor::Core;
+
+    use client::Client;
+    use lsp::LspClient;
+    use server::LspServer;
+    use test_util;
+
+    struct Stub;
+
+    impl LspServer for Stub {
+        fn initialize(&self, _params: InitializeParams) -> Box<Future<Item=InitializeResult, Error=RpcError>> {
+            Box::new(future::ok(serde_json::from_value(json!({"capabilities": {}})).unwrap()))
+        }
+    }
+
+    #[test]
+    fn test_loopback() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let addr = test_util::serve_tcp(Stub, &handle);
+
+        let connection = core.run(connect(&addr, &handle)).unwrap();
+        let mut client = Client::new(connection, &handle);
+        let result = core.run(client.initialize(test_util::init_params())).unwrap();
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_from_tcp() {
+        let mut core = Core::new().unwrap();
+        let handle = core.handle();
+        let addr = test_util::serve_tcp(Stub, &handle);
+
+        let stream = core.run(TcpStream::connect(&addr, &handle)).unwrap();
+        let mut client = Client::from_tcp(stream, &handle);
+        let result = core.run(client.initialize(test_util::init_params())).unwrap();
+        assert!(result.is_ok());
+    }
+}
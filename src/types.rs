@@ -0,0 +1,291 @@
+//! Requests from newer versions of the protocol, which the pinned
+//! `languageserver_types` does not define yet.
+//!
+//! Only the fields needed to round-trip the messages are modelled, with
+//! anything else kept as raw JSON in `data`.
+
+use ls_types::{Command, Diagnostic, Location, Position, Range, SymbolKind, TextDocumentIdentifier,
+               TextDocumentPositionParams, TextEdit};
+use ls_types::request::Request;
+use serde_json::Value;
+use url::Url;
+
+/// The request type for the method `$name`, like `lsp_request!`, but also
+/// covering the requests defined in this module.
+macro_rules! request {
+    ("textDocument/prepareCallHierarchy") => { $crate::types::CallHierarchyPrepare };
+    ("callHierarchy/incomingCalls") => { $crate::types::CallHierarchyIncomingCalls };
+    ("callHierarchy/outgoingCalls") => { $crate::types::CallHierarchyOutgoingCalls };
+    ("textDocument/semanticTokens/full") => { $crate::types::SemanticTokensFull };
+    ("textDocument/semanticTokens/range") => { $crate::types::SemanticTokensRange };
+    ("textDocument/diagnostic") => { $crate::types::DocumentDiagnosticRequest };
+    ("textDocument/inlayHint") => { $crate::types::InlayHintRequest };
+    ("inlayHint/resolve") => { $crate::types::InlayHintResolve };
+    ("workspaceSymbol/resolve") => { $crate::types::WorkspaceSymbolResolve };
+    ($name:tt) => { lsp_request!($name) };
+}
+
+/// An item in a call hierarchy, as returned by
+/// `textDocument/prepareCallHierarchy`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    pub range: Range,
+    pub selection_range: Range,
+    /// Kept by the server between preparing the hierarchy and the calls.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// The parameters of `callHierarchy/incomingCalls` and
+/// `callHierarchy/outgoingCalls`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct CallHierarchyCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+/// A call to the item, made from `from` at each of `from_ranges`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    pub from: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+/// A call from the item to `to`, made at each of `from_ranges`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCall {
+    pub to: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+/// The `textDocument/prepareCallHierarchy` request.
+pub enum CallHierarchyPrepare {}
+
+impl Request for CallHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<CallHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareCallHierarchy";
+}
+
+/// The `callHierarchy/incomingCalls` request.
+pub enum CallHierarchyIncomingCalls {}
+
+impl Request for CallHierarchyIncomingCalls {
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyIncomingCall>>;
+    const METHOD: &'static str = "callHierarchy/incomingCalls";
+}
+
+/// The `callHierarchy/outgoingCalls` request.
+pub enum CallHierarchyOutgoingCalls {}
+
+impl Request for CallHierarchyOutgoingCalls {
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyOutgoingCall>>;
+    const METHOD: &'static str = "callHierarchy/outgoingCalls";
+}
+
+/// The parameters of `textDocument/semanticTokens/full`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// The parameters of `textDocument/semanticTokens/range`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensRangeParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+/// The semantic tokens of a document, encoded as five integers per token
+/// relative to the previous one, as described by the spec.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokens {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_id: Option<String>,
+    pub data: Vec<u32>,
+}
+
+/// The `textDocument/semanticTokens/full` request.
+pub enum SemanticTokensFull {}
+
+impl Request for SemanticTokensFull {
+    type Params = SemanticTokensParams;
+    type Result = Option<SemanticTokens>;
+    const METHOD: &'static str = "textDocument/semanticTokens/full";
+}
+
+/// The `textDocument/semanticTokens/range` request.
+pub enum SemanticTokensRange {}
+
+impl Request for SemanticTokensRange {
+    type Params = SemanticTokensRangeParams;
+    type Result = Option<SemanticTokens>;
+    const METHOD: &'static str = "textDocument/semanticTokens/range";
+}
+
+/// The parameters of `textDocument/diagnostic`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentDiagnosticParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The identifier the server registered its diagnostics with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    /// The `result_id` of the last report for the document, so that the
+    /// server can answer that nothing has changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_result_id: Option<String>,
+}
+
+/// The diagnostics of a document, in full or unchanged since the previous
+/// report.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DocumentDiagnosticReport {
+    Full(FullDocumentDiagnosticReport),
+    Unchanged(UnchangedDocumentDiagnosticReport),
+}
+
+/// Every diagnostic of a document.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullDocumentDiagnosticReport {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_id: Option<String>,
+    pub items: Vec<Diagnostic>,
+}
+
+/// The diagnostics of a document have not changed since the report with
+/// `result_id`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnchangedDocumentDiagnosticReport {
+    pub result_id: String,
+}
+
+/// The `textDocument/diagnostic` request.
+pub enum DocumentDiagnosticRequest {}
+
+impl Request for DocumentDiagnosticRequest {
+    type Params = DocumentDiagnosticParams;
+    type Result = DocumentDiagnosticReport;
+    const METHOD: &'static str = "textDocument/diagnostic";
+}
+
+/// The parameters of `textDocument/inlayHint`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+/// A hint shown inline in a document, such as an inferred type.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: InlayHintLabel,
+    /// 1 for a type, 2 for a parameter name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kind: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_edits: Option<Vec<TextEdit>>,
+    /// A string or `MarkupContent`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding_left: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub padding_right: Option<bool>,
+    /// Kept by the server between the hint and `inlayHint/resolve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// The label of an `InlayHint`, either plain text or made up of parts.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum InlayHintLabel {
+    String(String),
+    Parts(Vec<InlayHintLabelPart>),
+}
+
+/// Part of the label of an `InlayHint`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct InlayHintLabelPart {
+    pub value: String,
+    /// A string or `MarkupContent`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tooltip: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+}
+
+/// The `textDocument/inlayHint` request.
+pub enum InlayHintRequest {}
+
+impl Request for InlayHintRequest {
+    type Params = InlayHintParams;
+    type Result = Option<Vec<InlayHint>>;
+    const METHOD: &'static str = "textDocument/inlayHint";
+}
+
+/// The `inlayHint/resolve` request.
+pub enum InlayHintResolve {}
+
+impl Request for InlayHintResolve {
+    type Params = InlayHint;
+    type Result = InlayHint;
+    const METHOD: &'static str = "inlayHint/resolve";
+}
+
+/// A symbol found by `workspace/symbol`, which may need resolving with
+/// `workspaceSymbol/resolve` to find its range.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<u32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+    pub location: WorkspaceSymbolLocation,
+    /// Kept by the server between the query and `workspaceSymbol/resolve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Where a `WorkspaceSymbol` is, with the range left out until resolved.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum WorkspaceSymbolLocation {
+    Location(Location),
+    Uri { uri: Url },
+}
+
+/// The `workspaceSymbol/resolve` request.
+pub enum WorkspaceSymbolResolve {}
+
+impl Request for WorkspaceSymbolResolve {
+    type Params = WorkspaceSymbol;
+    type Result = WorkspaceSymbol;
+    const METHOD: &'static str = "workspaceSymbol/resolve";
+}
@@ -0,0 +1,65 @@
+//! Conversions between filesystem paths and the `file://` URIs used to
+//! identify documents.
+
+use url::Url;
+
+use std::path::{Path, PathBuf};
+
+use super::LspError;
+
+/// Convert an absolute path to a `file://` URI, percent-encoding as needed.
+pub fn path_to_uri(path: &Path) -> Result<Url, LspError> {
+    Url::from_file_path(path)
+        .map_err(|()| LspError::InvalidParams(format!("not an absolute path: {}", path.display())))
+}
+
+/// Convert a `file://` URI back to a path.
+pub fn uri_to_path(uri: &Url) -> Result<PathBuf, LspError> {
+    if uri.scheme() != "file" {
+        return Err(LspError::InvalidParams(format!("not a file URI: {}", uri)));
+    }
+    uri.to_file_path()
+        .map_err(|()| LspError::InvalidParams(format!("not a valid file URI: {}", uri)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_path() {
+        let path = Path::new("/home/me/src/main.rs");
+        let uri = path_to_uri(path).unwrap();
+        assert_eq!(uri.as_str(), "file:///home/me/src/main.rs");
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+        assert!(path_to_uri(Path::new("src/main.rs")).is_err());
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_path() {
+        let path = Path::new(r"C:\Users\me\src\main.rs");
+        let uri = path_to_uri(path).unwrap();
+        assert_eq!(uri.as_str(), "file:///C:/Users/me/src/main.rs");
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+        // Some editors encode the drive letter colon
+        let encoded = Url::parse("file:///C%3A/Users/me/src/main.rs").unwrap();
+        assert_eq!(uri_to_path(&encoded).unwrap(), path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_path_with_space() {
+        let path = Path::new("/home/me/my project/main.rs");
+        let uri = path_to_uri(path).unwrap();
+        assert_eq!(uri.as_str(), "file:///home/me/my%20project/main.rs");
+        assert_eq!(uri_to_path(&uri).unwrap(), path);
+    }
+
+    #[test]
+    fn test_not_file_uri() {
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        assert!(uri_to_path(&uri).is_err());
+    }
+}